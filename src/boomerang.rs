@@ -3,16 +3,18 @@ use fixed_macro::fixed;
 use serde::{Deserialize, Serialize};
 
 use crate::game::{
-    INPUT_ATTACK, INPUT_DOWN, INPUT_LEFT, INPUT_RIGHT, INPUT_UP,
+    INPUT_ATTACK, INPUT_DOWN, INPUT_LEFT, INPUT_RIGHT, INPUT_UP, NO_AIM,
 };
+use crate::level::Level;
 use crate::player::{Player, OG_FPS};
+use crate::ruleset::{Ruleset, WallBehavior};
 use crate::utils::{
-    do_hitboxes_overlap, input_check, input_pressed, lerp, Hitbox,
-    IntVector2D,
+    aim_heading, do_hitboxes_overlap, input_check, input_pressed,
+    input_released, lerp, Hitbox, IntVector2D,
 };
 
-pub const MAX_SPEED: i32 = 300 * 1000;
-pub const RETURN_RATE: I32F32 = fixed!(0.75: I32F32);
+// boomerangs are square
+pub const SIZE: i32 = 8000;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Boomerang {
@@ -22,8 +24,27 @@ pub struct Boomerang {
     pub current_animation: String,
     pub current_animation_frame: usize,
     pub is_holstered: bool,
+    // embedded in a wall until the owner recalls it
+    pub is_stuck: bool,
+    // homing straight back to the owner, ignoring walls
+    pub is_returning: bool,
+    // knocked away by a clash, and harmless until caught
+    pub is_deflected: bool,
+    // sent back by a parry, and dangerous to the thrower's team instead
+    // of their opponents until caught
+    pub is_reflected: bool,
+    // attack is held down while holstered, and the throw happens on
+    // release
+    pub is_charging: bool,
+    pub charge_frames: i32,
+    // set from the charge level when thrown
+    pub throw_speed: i32,
+    // in thousandths, like the ruleset's
+    pub return_rate: i32,
     pub flight_time: i32,
-    pub collided_with_player: bool,
+    // the player nums this boomerang touched this frame
+    pub collided_with_players: Vec<usize>,
+    pub particle_spawns: Vec<(IntVector2D, String)>,
     pub sound_commands: Vec<(String, String, i32)>,
 }
 
@@ -33,16 +54,25 @@ impl Boomerang {
             hitbox: Hitbox {
                 x: 0,
                 y: 0,
-                width: 8000,
-                height: 8000,
+                width: SIZE,
+                height: SIZE,
             },
             velocity: IntVector2D { x: 0, y: 0 },
             initial_velocity: IntVector2D { x: 0, y: 0 },
             current_animation: "idle".to_string(),
             current_animation_frame: 0,
             is_holstered: true,
+            is_stuck: false,
+            is_returning: false,
+            is_deflected: false,
+            is_reflected: false,
+            is_charging: false,
+            charge_frames: 0,
+            throw_speed: 0,
+            return_rate: 0,
             flight_time: 0,
-            collided_with_player: false,
+            collided_with_players: Vec::new(),
+            particle_spawns: Vec::new(),
             sound_commands: Vec::new(),
         };
     }
@@ -51,41 +81,67 @@ impl Boomerang {
         &mut self,
         input: u8,
         prev_input: u8,
+        aim: u8,
         player: &Player,
-        other_player_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        level: &Level,
+        ruleset: &Ruleset,
+        // added to the throw speed during sudden death
+        speedup: i32,
     ) {
-        self.collided_with_player = false;
-        if input_pressed(INPUT_ATTACK, input, prev_input)
-            && self.is_holstered
+        self.collided_with_players.clear();
+        let is_attack_pressed =
+            input_pressed(INPUT_ATTACK, input, prev_input);
+        if is_attack_pressed && self.is_stuck {
+            self.is_stuck = false;
+            self.is_returning = true;
+        } else if is_attack_pressed
+            && ruleset.boomerang_manual_recall
+            && !self.is_holstered
         {
-            let mut attack_heading = IntVector2D { x: 1, y: 0 };
-            if player.is_facing_left {
-                attack_heading.x = -1;
-            }
-            if input_check(INPUT_LEFT, input) {
-                attack_heading.x = -1;
-            } else if input_check(INPUT_RIGHT, input) {
-                attack_heading.x = 1;
-            } else if input_check(INPUT_UP, input)
-                || input_check(INPUT_DOWN, input)
+            self.is_returning = true;
+        }
+        if self.is_holstered {
+            if ruleset.boomerang_charge_levels == 0 {
+                if is_attack_pressed {
+                    self.throw(input, aim, player, 0, speedup, ruleset);
+                }
+            } else if is_attack_pressed {
+                self.is_charging = true;
+                self.charge_frames = 0;
+            } else if self.is_charging
+                && input_released(INPUT_ATTACK, input, prev_input)
             {
-                attack_heading.x = 0;
-            }
-            if input_check(INPUT_UP, input) {
-                attack_heading.y = -1;
-            } else if input_check(INPUT_DOWN, input) {
-                attack_heading.y = 1;
+                let charge_level = self.charge_level(ruleset);
+                self.is_charging = false;
+                self.throw(
+                    input,
+                    aim,
+                    player,
+                    charge_level,
+                    speedup,
+                    ruleset,
+                );
+            } else if self.is_charging {
+                self.charge_frames = std::cmp::min(
+                    self.charge_frames + 1,
+                    ruleset.boomerang_charge_levels
+                        * ruleset.boomerang_frames_per_charge_level,
+                );
             }
-            self.velocity = attack_heading;
-            self.velocity.normalize(MAX_SPEED);
-            self.initial_velocity = self.velocity.clone();
-            self.is_holstered = false;
-            self.add_sound_command("toss", "play", 100);
         }
         if self.is_holstered {
             self.hitbox.x = player.center_x() - self.hitbox.width / 2;
             self.hitbox.y = player.center_y() - self.hitbox.height / 2;
             self.flight_time = 0;
+            self.is_stuck = false;
+            self.is_returning = false;
+            self.is_deflected = false;
+            self.is_reflected = false;
+            self.add_sound_command("whoosh", "stop", 100);
+        } else if self.is_stuck {
+            self.velocity.zero();
+            self.check_entity_collisions(opponent_hurtboxes);
             self.add_sound_command("whoosh", "stop", 100);
         } else {
             let mut towards_player = IntVector2D {
@@ -93,13 +149,16 @@ impl Boomerang {
                 y: player.center_y() - self.center_y(),
             };
             let distance_from_player = towards_player.length_as_int();
-            towards_player.normalize(MAX_SPEED);
+            towards_player.normalize(self.throw_speed);
 
+            let return_rate = I32F32::from_num(self.return_rate)
+                .saturating_div(I32F32::from_num(1000));
             let mut lerp_factor = I32F32::from_num(self.flight_time)
                 .saturating_div(I32F32::from_num(OG_FPS))
-                .saturating_mul(RETURN_RATE);
-            if lerp_factor > I32F32::ONE {
+                .saturating_mul(return_rate);
+            if lerp_factor > I32F32::ONE || self.is_returning {
                 lerp_factor = I32F32::ONE;
+                self.is_returning = true;
             }
 
             self.velocity.x = lerp(
@@ -113,7 +172,7 @@ impl Boomerang {
                 lerp_factor,
             );
 
-            towards_player.normalize(MAX_SPEED / OG_FPS);
+            towards_player.normalize(self.throw_speed / OG_FPS);
 
             if self.flight_time > 6
                 && towards_player.length_as_int() >= distance_from_player
@@ -123,16 +182,18 @@ impl Boomerang {
                 self.add_sound_command("catch", "play", 100);
             } else {
                 self.move_by(
+                    level,
+                    ruleset,
                     self.velocity.x / OG_FPS,
                     self.velocity.y / OG_FPS,
-                    other_player_hitbox,
+                    opponent_hurtboxes,
                 );
                 self.flight_time += 1;
             }
             let whoosh_volume = self
                 .velocity
                 .length()
-                .saturating_div(I32F32::from_num(MAX_SPEED))
+                .saturating_div(I32F32::from_num(self.throw_speed))
                 .saturating_mul(fixed!(100: I32F32))
                 .saturating_to_num::<i32>();
             self.add_sound_command("whoosh", "loop", whoosh_volume);
@@ -140,47 +201,190 @@ impl Boomerang {
         self.current_animation_frame += 1;
     }
 
+    // 0 for a tap, up to the ruleset's number of charge levels
+    pub fn charge_level(&self, ruleset: &Ruleset) -> i32 {
+        if !self.is_charging {
+            return 0;
+        }
+        return self.charge_frames
+            / std::cmp::max(ruleset.boomerang_frames_per_charge_level, 1);
+    }
+
+    fn throw(
+        &mut self,
+        input: u8,
+        aim: u8,
+        player: &Player,
+        charge_level: i32,
+        speedup: i32,
+        ruleset: &Ruleset,
+    ) {
+        let mut attack_heading = IntVector2D { x: 1, y: 0 };
+        if player.is_facing_left {
+            attack_heading.x = -1;
+        }
+        if input_check(INPUT_LEFT, input) {
+            attack_heading.x = -1;
+        } else if input_check(INPUT_RIGHT, input) {
+            attack_heading.x = 1;
+        } else if input_check(INPUT_UP, input)
+            || input_check(INPUT_DOWN, input)
+        {
+            attack_heading.x = 0;
+        }
+        if input_check(INPUT_UP, input) {
+            attack_heading.y = -1;
+        } else if input_check(INPUT_DOWN, input) {
+            attack_heading.y = 1;
+        }
+        if aim != NO_AIM {
+            attack_heading = aim_heading(aim - 1, 1000);
+        }
+        // a full charge throws at the charged values, a tap at the base
+        // ones, and levels in between are spread evenly
+        let max_level = std::cmp::max(ruleset.boomerang_charge_levels, 1);
+        self.throw_speed = ruleset.boomerang_max_speed
+            + (ruleset.boomerang_charged_max_speed
+                - ruleset.boomerang_max_speed)
+                * charge_level
+                / max_level
+            + speedup;
+        self.return_rate = ruleset.boomerang_return_rate
+            + (ruleset.boomerang_charged_return_rate
+                - ruleset.boomerang_return_rate)
+                * charge_level
+                / max_level;
+        self.velocity = attack_heading;
+        self.velocity.normalize(self.throw_speed);
+        self.initial_velocity = self.velocity;
+        self.is_holstered = false;
+        self.add_sound_command("toss", "play", 100);
+    }
+
     pub fn move_by(
         &mut self,
+        level: &Level,
+        ruleset: &Ruleset,
         move_x: i32,
         move_y: i32,
-        other_player_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
     ) {
+        // walls only stop the outward part of a throw, so the boomerang
+        // can always find its way back
+        let collides_with_level = ruleset.boomerang_wall_behavior
+            != WallBehavior::Pass
+            && !self.is_returning;
         let mut sign = if move_x > 0 { 1 } else { -1 };
         let increments = [1000, 100, 10, 1];
         let mut increment_index = 0;
         let mut move_amount = move_x.abs();
-        while increment_index < increments.len() {
+        'move_x: while increment_index < increments.len() {
             while move_amount >= increments[increment_index] {
-                self.hitbox.x += increments[increment_index] * sign;
-                self.check_entity_collisions(other_player_hitbox);
+                let mut next_hitbox = self.hitbox.clone();
+                next_hitbox.x += increments[increment_index] * sign;
+                if collides_with_level && level.collide(&next_hitbox) {
+                    self.hit_wall(ruleset, true);
+                    break 'move_x;
+                }
+                self.hitbox = next_hitbox;
+                self.check_entity_collisions(opponent_hurtboxes);
                 move_amount -= increments[increment_index];
             }
             increment_index += 1;
         }
+        if self.is_stuck {
+            return;
+        }
 
         sign = if move_y > 0 { 1 } else { -1 };
         increment_index = 0;
         move_amount = move_y.abs();
-        while increment_index < increments.len() {
+        'move_y: while increment_index < increments.len() {
             while move_amount >= increments[increment_index] {
-                self.hitbox.y += increments[increment_index] * sign;
-                self.check_entity_collisions(other_player_hitbox);
+                let mut next_hitbox = self.hitbox.clone();
+                next_hitbox.y += increments[increment_index] * sign;
+                if collides_with_level && level.collide(&next_hitbox) {
+                    self.hit_wall(ruleset, false);
+                    break 'move_y;
+                }
+                self.hitbox = next_hitbox;
+                self.check_entity_collisions(opponent_hurtboxes);
                 move_amount -= increments[increment_index];
             }
             increment_index += 1;
         }
     }
 
+    fn hit_wall(&mut self, ruleset: &Ruleset, is_horizontal: bool) {
+        match ruleset.boomerang_wall_behavior {
+            WallBehavior::Bounce => {
+                // the initial velocity is flipped too, since the flight
+                // path is blended from it every frame
+                if is_horizontal {
+                    self.velocity.x = -self.velocity.x;
+                    self.initial_velocity.x = -self.initial_velocity.x;
+                } else {
+                    self.velocity.y = -self.velocity.y;
+                    self.initial_velocity.y = -self.initial_velocity.y;
+                }
+            }
+            WallBehavior::Stick => {
+                self.is_stuck = true;
+                self.velocity.zero();
+            }
+            WallBehavior::Pass => (),
+        }
+        self.add_sound_command("bounce", "play", 100);
+        self.particle_spawns.push((
+            IntVector2D {
+                x: self.center_x(),
+                y: self.center_y(),
+            },
+            "grounddust".to_string(),
+        ));
+    }
+
     pub fn check_entity_collisions(
         &mut self,
-        other_player_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
     ) {
-        if do_hitboxes_overlap(&self.hitbox, other_player_hitbox) {
-            self.collided_with_player = true;
+        for (player_num, hitbox) in opponent_hurtboxes {
+            if do_hitboxes_overlap(&self.hitbox, hitbox)
+                && !self.collided_with_players.contains(player_num)
+            {
+                self.collided_with_players.push(*player_num);
+            }
         }
     }
 
+    pub fn is_in_flight(&self) -> bool {
+        return !self.is_holstered && !self.is_stuck;
+    }
+
+    pub fn is_dangerous(&self) -> bool {
+        return !self.is_holstered && !self.is_deflected;
+    }
+
+    pub fn deflect(&mut self) {
+        self.is_returning = true;
+        self.is_deflected = true;
+        self.is_reflected = false;
+    }
+
+    // turned around the way it came. it curves home as if just thrown,
+    // so it flies back through whoever threw it
+    pub fn reflect(&mut self) {
+        self.velocity = IntVector2D {
+            x: -self.velocity.x,
+            y: -self.velocity.y,
+        };
+        self.initial_velocity = self.velocity;
+        self.flight_time = 0;
+        self.is_stuck = false;
+        self.is_returning = false;
+        self.is_reflected = true;
+    }
+
     pub fn center_x(&self) -> i32 {
         return self.hitbox.x + self.hitbox.width / 2;
     }
@@ -202,3 +406,104 @@ impl Boomerang {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // feeds the boomerang one input per frame, held by a player standing
+    // at the first start
+    fn hold(boomerang: &mut Boomerang, inputs: &[u8], ruleset: &Ruleset) {
+        let level = Level::new();
+        let start = level.player_starts[0];
+        let player = Player::new(start.x, start.y - 1, false);
+        let mut prev_input = 0;
+        for input in inputs {
+            boomerang.advance(
+                *input,
+                prev_input,
+                NO_AIM,
+                &player,
+                &[],
+                &level,
+                ruleset,
+                0,
+            );
+            prev_input = *input;
+        }
+    }
+
+    fn charge_ruleset() -> Ruleset {
+        return Ruleset {
+            boomerang_charge_levels: 2,
+            boomerang_manual_recall: true,
+            ..Ruleset::default()
+        };
+    }
+
+    #[test]
+    fn classic_throws_on_the_press() {
+        let ruleset = Ruleset::default();
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &[INPUT_ATTACK], &ruleset);
+        assert!(!boomerang.is_holstered);
+        assert_eq!(boomerang.throw_speed, ruleset.boomerang_max_speed);
+    }
+
+    #[test]
+    fn a_tap_throws_at_the_base_speed() {
+        let ruleset = charge_ruleset();
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &[INPUT_ATTACK], &ruleset);
+        assert!(boomerang.is_holstered);
+        hold(&mut boomerang, &[INPUT_ATTACK, 0], &ruleset);
+        assert!(!boomerang.is_holstered);
+        assert_eq!(boomerang.throw_speed, ruleset.boomerang_max_speed);
+        assert_eq!(boomerang.return_rate, ruleset.boomerang_return_rate);
+    }
+
+    #[test]
+    fn holding_attack_charges_up_to_the_last_level() {
+        let ruleset = charge_ruleset();
+        let max_level = ruleset.boomerang_charge_levels;
+        let frames =
+            (max_level + 1) * ruleset.boomerang_frames_per_charge_level;
+        let mut inputs = vec![INPUT_ATTACK; frames as usize];
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &inputs, &ruleset);
+        assert!(boomerang.is_holstered);
+        assert_eq!(boomerang.charge_level(&ruleset), max_level);
+
+        inputs.push(0);
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &inputs, &ruleset);
+        assert!(!boomerang.is_holstered);
+        assert_eq!(
+            boomerang.throw_speed,
+            ruleset.boomerang_charged_max_speed
+        );
+        assert_eq!(
+            boomerang.return_rate,
+            ruleset.boomerang_charged_return_rate
+        );
+    }
+
+    #[test]
+    fn pressing_attack_with_the_boomerang_out_recalls_it() {
+        let ruleset = charge_ruleset();
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &[INPUT_ATTACK, 0, 0], &ruleset);
+        assert!(!boomerang.is_returning);
+        hold(&mut boomerang, &[INPUT_ATTACK], &ruleset);
+        assert!(boomerang.is_returning);
+
+        let no_recall = Ruleset {
+            boomerang_manual_recall: false,
+            ..charge_ruleset()
+        };
+        let mut boomerang = Boomerang::new();
+        hold(&mut boomerang, &[INPUT_ATTACK, 0, 0], &no_recall);
+        hold(&mut boomerang, &[INPUT_ATTACK], &no_recall);
+        assert!(!boomerang.is_returning);
+    }
+}
@@ -1,23 +1,76 @@
 use serde::{Deserialize, Serialize};
 
-pub const MAX_OPACITY: i32 = 100;
+use crate::utils::IntVector2D;
 
+pub const FADE_IN_FRAMES: i32 = 100;
+pub const WIPE_FRAMES: i32 = 30;
+pub const ROUND_END_FRAMES: i32 = 45;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Transition {
+    Fade,
+    // a black bar across the screen from left to right
+    Wipe,
+    // a shrinking circle around `center`
+    Iris,
+}
+
+// a transition covering or uncovering the screen. it's part of the
+// simulation, so it's started on the same frame everywhere
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Curtain {
-    pub opacity: i32,
+    pub transition: Transition,
+    pub is_closing: bool,
+    pub center: IntVector2D,
+    frames: i32,
+    frames_left: i32,
 }
 
 impl Curtain {
+    // the screen fades in when the match starts
     pub fn new() -> Curtain {
-        return Curtain {
-            opacity: MAX_OPACITY,
+        let mut curtain = Curtain {
+            transition: Transition::Fade,
+            is_closing: false,
+            center: IntVector2D { x: 0, y: 0 },
+            frames: 0,
+            frames_left: 0,
         };
+        curtain.open(Transition::Fade, FADE_IN_FRAMES);
+        return curtain;
+    }
+
+    pub fn open(&mut self, transition: Transition, frames: i32) {
+        self.start(transition, false, frames);
+    }
+
+    pub fn close(&mut self, transition: Transition, frames: i32) {
+        self.start(transition, true, frames);
+    }
+
+    fn start(
+        &mut self,
+        transition: Transition,
+        is_closing: bool,
+        frames: i32,
+    ) {
+        self.transition = transition;
+        self.is_closing = is_closing;
+        self.frames = std::cmp::max(frames, 1);
+        self.frames_left = self.frames;
     }
 
     pub fn advance(&mut self) {
-        self.opacity -= 1;
-        if self.opacity < 0 {
-            self.opacity = 0;
+        self.frames_left = std::cmp::max(self.frames_left - 1, 0);
+    }
+
+    // how much of the screen is hidden, from 0 to 1
+    pub fn coverage(&self) -> f32 {
+        let done =
+            (self.frames - self.frames_left) as f32 / self.frames as f32;
+        if self.is_closing {
+            return done;
         }
+        return 1.0 - done;
     }
 }
@@ -0,0 +1,217 @@
+use tetra::graphics::{Color, DrawParams};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::level::TILE_SIZE;
+use crate::player::OG_FPS;
+use crate::utils::Hitbox;
+use crate::{world_to_screen, Match, Resources};
+
+const TERRAIN_COLOR: Color = Color::rgba(0.3, 1.0, 0.3, 0.9);
+const HURTBOX_COLOR: Color = Color::rgba(1.0, 0.25, 0.2, 0.9);
+const BOOMERANG_COLOR: Color = Color::rgba(1.0, 0.9, 0.35, 0.9);
+const TILE_COLOR: Color = Color::rgba(0.2, 0.4, 1.0, 0.8);
+const SOLID_TILE_COLOR: Color = Color::rgba(0.2, 0.4, 1.0, 0.35);
+// velocity lines show where something will be this many frames on
+const VELOCITY_FRAMES: i32 = 8;
+
+// what the simulation sees rather than what's drawn, for working out
+// why a movement tech did or didn't come out. positions come straight
+// from the state, without any smoothing
+pub fn draw(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    let state = &current_match.game.state;
+    for player in &state.players {
+        if player.is_dead {
+            continue;
+        }
+        // the ground and wall checks each frame, and the move itself
+        let step_x = player.velocity.x / OG_FPS;
+        let step_y = player.velocity.y / OG_FPS;
+        let mut tiles: Vec<(i32, i32)> = Vec::new();
+        for (offset_x, offset_y) in
+            [(0, 1), (-1, 0), (1, 0), (step_x, step_y)]
+        {
+            let probe = Hitbox {
+                x: player.hitbox.x + offset_x,
+                y: player.hitbox.y + offset_y,
+                width: player.hitbox.width,
+                height: player.hitbox.height,
+            };
+            for tile in state.level.checked_tiles(&probe) {
+                if !tiles.contains(&tile) {
+                    tiles.push(tile);
+                }
+            }
+        }
+        for (tile_x, tile_y) in tiles {
+            let tile = Hitbox {
+                x: tile_x * TILE_SIZE,
+                y: tile_y * TILE_SIZE,
+                width: TILE_SIZE,
+                height: TILE_SIZE,
+            };
+            if state.level.check_grid(tile_x, tile_y) {
+                draw_box(ctx, resources, &tile, SOLID_TILE_COLOR);
+            }
+            draw_outline(ctx, resources, &tile, TILE_COLOR);
+        }
+
+        draw_outline(ctx, resources, &player.hitbox, TERRAIN_COLOR);
+        draw_outline(ctx, resources, &player.hurtbox(), HURTBOX_COLOR);
+        draw_velocity(
+            ctx,
+            resources,
+            player.center_x(),
+            player.center_y(),
+            step_x,
+            step_y,
+        );
+
+        let lines = [
+            player.current_animation.to_uppercase(),
+            format!(
+                "DODGE {} CD {}",
+                player.dodge_timer, player.dodge_cooldown
+            ),
+            format!(
+                "DJ {} SJ {}",
+                yes_no(player.can_double_jump),
+                yes_no(player.is_super_jumping)
+            ),
+        ];
+        let line_height = resources.font.height(1) + 1.0;
+        let top = world_to_screen(player.hitbox.y)
+            - line_height * lines.len() as f32
+            - 2.0;
+        for (line_num, line) in lines.iter().enumerate() {
+            resources.font.draw_centered(
+                ctx,
+                line,
+                world_to_screen(player.center_x()),
+                top + line_num as f32 * line_height,
+                1,
+                Color::WHITE,
+            );
+        }
+    }
+
+    for boomerang in &state.boomerangs {
+        if boomerang.is_holstered {
+            continue;
+        }
+        draw_outline(ctx, resources, &boomerang.hitbox, BOOMERANG_COLOR);
+        draw_velocity(
+            ctx,
+            resources,
+            boomerang.center_x(),
+            boomerang.center_y(),
+            boomerang.velocity.x / OG_FPS,
+            boomerang.velocity.y / OG_FPS,
+        );
+    }
+
+    resources.font.draw(
+        ctx,
+        &format!("FRAME {}", state.frame),
+        Vec2::new(4.0, 170.0),
+        1,
+        Color::WHITE,
+    );
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        return "Y";
+    }
+    return "N";
+}
+
+fn draw_box(
+    ctx: &mut Context,
+    resources: &Resources,
+    hitbox: &Hitbox,
+    color: Color,
+) {
+    resources.rectangle.draw(
+        ctx,
+        DrawParams::new()
+            .position(Vec2::new(
+                world_to_screen(hitbox.x),
+                world_to_screen(hitbox.y),
+            ))
+            .scale(Vec2::new(
+                world_to_screen(hitbox.width),
+                world_to_screen(hitbox.height),
+            ))
+            .color(color),
+    );
+}
+
+// a one pixel border just inside the hitbox
+fn draw_outline(
+    ctx: &mut Context,
+    resources: &Resources,
+    hitbox: &Hitbox,
+    color: Color,
+) {
+    let pixel = 1000;
+    let edges = [
+        (hitbox.x, hitbox.y, hitbox.width, pixel),
+        (
+            hitbox.x,
+            hitbox.y + hitbox.height - pixel,
+            hitbox.width,
+            pixel,
+        ),
+        (hitbox.x, hitbox.y, pixel, hitbox.height),
+        (
+            hitbox.x + hitbox.width - pixel,
+            hitbox.y,
+            pixel,
+            hitbox.height,
+        ),
+    ];
+    for (x, y, width, height) in edges {
+        let edge = Hitbox {
+            x,
+            y,
+            width,
+            height,
+        };
+        draw_box(ctx, resources, &edge, color);
+    }
+}
+
+// a line from the center along the distance moved per frame
+fn draw_velocity(
+    ctx: &mut Context,
+    resources: &Resources,
+    center_x: i32,
+    center_y: i32,
+    step_x: i32,
+    step_y: i32,
+) {
+    let line = Vec2::new(
+        world_to_screen(step_x * VELOCITY_FRAMES),
+        world_to_screen(step_y * VELOCITY_FRAMES),
+    );
+    if line.magnitude() < 1.0 {
+        return;
+    }
+    resources.rectangle.draw(
+        ctx,
+        DrawParams::new()
+            .position(Vec2::new(
+                world_to_screen(center_x),
+                world_to_screen(center_y),
+            ))
+            .origin(Vec2::new(0.0, 0.5))
+            .scale(Vec2::new(line.magnitude(), 1.0))
+            .rotation(line.y.atan2(line.x))
+            .color(Color::WHITE),
+    );
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use crate::particle::{get_emitter, ParticleSystem};
+use crate::utils::{aim_heading, IntVector2D, AIM_DIRECTIONS};
+
+// spawned effects are remembered this many frames so a rollback can
+// cancel or confirm them
+const EFFECT_MEMORY_FRAMES: i32 = 60;
+pub const AFTERIMAGE_FRAMES: i32 = 12;
+pub const FLASH_FRAMES: i32 = 6;
+// screen shake is in thousandths of a pixel, and loses this much a frame
+const SHAKE_DECAY: i32 = 500;
+
+// how hard each kind of kill shakes the screen
+const KILL_SHAKES: [(&str, i32); 3] = [
+    ("kill_boomerang", 4000),
+    ("kill_dodge", 6000),
+    ("kill_crush", 3000),
+];
+
+// a cosmetic effect the simulation asked for on a frame. the particles
+// themselves live outside the rollback state
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Effect {
+    pub frame: i32,
+    pub emitter: String,
+    pub position: IntVector2D,
+}
+
+// a fading copy of a sprite left behind something moving fast. these
+// are sampled from whatever state is on screen, so rollbacks need no
+// special handling
+pub struct Afterimage {
+    pub sprite: String,
+    pub animation: String,
+    pub animation_frame: usize,
+    pub center: IntVector2D,
+    pub is_facing_left: bool,
+    pub age: i32,
+}
+
+struct SpawnedEffect {
+    effect: Effect,
+    id: u32,
+}
+
+// plays the effects the simulation emits. when frames are rolled back
+// and simulated again, effects emitted again are kept as they are and
+// ones that were only predicted are taken back
+pub struct Effects {
+    pub particles: ParticleSystem,
+    pub afterimages: Vec<Afterimage>,
+    // left over from the last kill, fading every frame
+    pub shake: i32,
+    pub flash_frames: i32,
+    shake_frame: i32,
+    spawned: Vec<SpawnedEffect>,
+    next_id: u32,
+}
+
+impl Effects {
+    pub fn new() -> Effects {
+        return Effects {
+            particles: ParticleSystem::new(),
+            afterimages: Vec::new(),
+            shake: 0,
+            flash_frames: 0,
+            shake_frame: 0,
+            spawned: Vec::new(),
+            next_id: 0,
+        };
+    }
+
+    // rollback_frame is the earliest frame the simulation went back to
+    // since the last sync, and effects are everything emitted since
+    pub fn sync(
+        &mut self,
+        rollback_frame: Option<i32>,
+        effects: Vec<Effect>,
+    ) {
+        let mut unconfirmed: Vec<usize> = match rollback_frame {
+            Some(rollback_frame) => (0..self.spawned.len())
+                .filter(|index| {
+                    self.spawned[*index].effect.frame > rollback_frame
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut latest_frame = -1;
+        for effect in effects {
+            latest_frame = std::cmp::max(latest_frame, effect.frame);
+            let confirmed = unconfirmed
+                .iter()
+                .position(|index| self.spawned[*index].effect == effect);
+            if let Some(confirmed) = confirmed {
+                unconfirmed.remove(confirmed);
+                continue;
+            }
+            self.spawn(effect);
+        }
+        // anything left was on a mispredicted frame
+        for index in unconfirmed.into_iter().rev() {
+            let spawned = self.spawned.remove(index);
+            self.particles.cancel(spawned.id);
+        }
+        if latest_frame != -1 {
+            self.spawned.retain(|spawned| {
+                spawned.effect.frame > latest_frame - EFFECT_MEMORY_FRAMES
+            });
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.particles.advance();
+        for afterimage in self.afterimages.iter_mut() {
+            afterimage.age += 1;
+        }
+        self.afterimages
+            .retain(|afterimage| afterimage.age < AFTERIMAGE_FRAMES);
+        self.shake = std::cmp::max(self.shake - SHAKE_DECAY, 0);
+        self.flash_frames = std::cmp::max(self.flash_frames - 1, 0);
+        self.shake_frame += 1;
+    }
+
+    // how far to move the screen this frame. each frame jumps most of
+    // the way around from the last so the shake doesn't drift
+    pub fn shake_offset(&self) -> IntVector2D {
+        if self.shake == 0 {
+            return IntVector2D { x: 0, y: 0 };
+        }
+        let direction = (self.shake_frame * 23)
+            .rem_euclid(AIM_DIRECTIONS as i32)
+            as u8;
+        return aim_heading(direction, self.shake);
+    }
+
+    pub fn add_afterimage(
+        &mut self,
+        sprite: &str,
+        animation: &str,
+        animation_frame: usize,
+        center: IntVector2D,
+        is_facing_left: bool,
+    ) {
+        self.afterimages.push(Afterimage {
+            sprite: sprite.to_string(),
+            animation: animation.to_string(),
+            animation_frame,
+            center,
+            is_facing_left,
+            age: 0,
+        });
+    }
+
+    fn spawn(&mut self, effect: Effect) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if let Some(emitter) = get_emitter(&effect.emitter) {
+            self.particles.emit(emitter, effect.position, id);
+        }
+        if let Some((_, shake)) =
+            KILL_SHAKES.iter().find(|(name, _)| *name == effect.emitter)
+        {
+            self.shake = std::cmp::max(self.shake, *shake);
+            self.flash_frames = FLASH_FRAMES;
+        }
+        self.spawned.push(SpawnedEffect { effect, id });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explosion(frame: i32, x: i32) -> Effect {
+        return Effect {
+            frame,
+            emitter: "explosion".to_string(),
+            position: IntVector2D { x, y: 0 },
+        };
+    }
+
+    fn count(effects: &Effects) -> usize {
+        return effects.particles.iter().count();
+    }
+
+    #[test]
+    fn effects_simulated_again_after_a_rollback_are_not_doubled() {
+        let mut effects = Effects::new();
+        effects.sync(None, vec![explosion(10, 0)]);
+        let burst = count(&effects);
+        assert!(burst > 0);
+        effects.sync(Some(8), vec![explosion(10, 0)]);
+        assert_eq!(count(&effects), burst);
+    }
+
+    #[test]
+    fn mispredicted_effects_are_cancelled() {
+        let mut effects = Effects::new();
+        effects.sync(None, vec![explosion(5, 0)]);
+        let burst = count(&effects);
+        effects.sync(None, vec![explosion(10, 0)]);
+        assert_eq!(count(&effects), burst * 2);
+
+        // frame 10 is simulated again with the explosion somewhere else
+        effects.sync(Some(8), vec![explosion(10, 1000)]);
+        assert_eq!(count(&effects), burst * 2);
+        assert!(effects
+            .particles
+            .iter()
+            .all(|particle| particle.effect_id != 1));
+
+        // and then without it at all. frame 5 was before the rollback,
+        // so it stays
+        effects.sync(Some(8), Vec::new());
+        assert_eq!(count(&effects), burst);
+        assert!(effects
+            .particles
+            .iter()
+            .all(|particle| particle.effect_id == 0));
+    }
+}
@@ -4,20 +4,27 @@ use ggrs::{
     NULL_FRAME,
 };
 use serde::{Deserialize, Serialize};
-use serde_big_array::BigArray;
 use std::net::SocketAddr;
 use tetra::input::{self, GamepadAxis, GamepadButton, Key};
 use tetra::Context;
 
-use crate::boomerang::Boomerang;
-use crate::curtain::Curtain;
-use crate::level::Level;
-use crate::particle::Particle;
-use crate::player::Player;
-use crate::utils::IntVector2D;
+use crate::boomerang::{self, Boomerang};
+use crate::curtain::{Curtain, Transition, ROUND_END_FRAMES, WIPE_FRAMES};
+use crate::effects::Effect;
+use crate::handshake::MatchInfo;
+use crate::hurtbox::Hurtboxes;
+use crate::level::{Level, TILE_SIZE};
+use crate::player::{self, Player, OG_FPS};
+use crate::ruleset::{Ruleset, SuddenDeath, TradeRule};
+use crate::utils::{
+    do_hitboxes_overlap, fnv1a_hash, Hitbox, IntVector2D, AIM_DIRECTIONS,
+};
 
 const CHECKSUM_PERIOD: i32 = 100;
 
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 4;
+
 pub const INPUT_UP: u8 = 1 << 0;
 pub const INPUT_DOWN: u8 = 1 << 1;
 pub const INPUT_LEFT: u8 = 1 << 2;
@@ -26,12 +33,50 @@ pub const INPUT_JUMP: u8 = 1 << 4;
 pub const INPUT_ATTACK: u8 = 1 << 5;
 pub const INPUT_DODGE: u8 = 1 << 6;
 
+struct KeyboardLayout {
+    up: Key,
+    down: Key,
+    left: Key,
+    right: Key,
+    jump: Key,
+    attack: Key,
+    dodge: Key,
+}
+
+// one layout per local player sharing the keyboard, any further local
+// players need a gamepad
+const KEYBOARD_LAYOUTS: [KeyboardLayout; 2] = [
+    KeyboardLayout {
+        up: Key::Up,
+        down: Key::Down,
+        left: Key::Left,
+        right: Key::Right,
+        jump: Key::Z,
+        attack: Key::X,
+        dodge: Key::C,
+    },
+    KeyboardLayout {
+        up: Key::W,
+        down: Key::S,
+        left: Key::A,
+        right: Key::D,
+        jump: Key::G,
+        attack: Key::H,
+        dodge: Key::J,
+    },
+];
+
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
 pub struct Input {
     pub inp: u8,
+    // NO_AIM, or one more than the analog stick's aim direction
+    pub aim: u8,
 }
 
+pub const NO_AIM: u8 = 0;
+const AIM_DEADZONE: f32 = 0.5;
+
 // GGRSConfig holds all type parameters for GGRS Sessions
 #[derive(Debug)]
 pub struct GGRSConfig;
@@ -53,24 +98,56 @@ fn fletcher16(data: &[u8]) -> u16 {
     (sum2 << 8) | sum1
 }
 
+// hashes the constants that change how the simulation plays out but
+// aren't part of the ruleset
+fn constants_hash() -> u64 {
+    let constants = [
+        player::OG_FPS,
+        player::WIDTH,
+        player::HEIGHT,
+        boomerang::SIZE,
+    ];
+    return fnv1a_hash(&bincode::serialize(&constants[..]).unwrap());
+}
+
 pub struct Game {
     pub state: State,
     pub level: Level,
+    pub ruleset: Ruleset,
+    pub hurtboxes: Hurtboxes,
+    // effects from every frame advanced since take_effects, and the
+    // earliest frame loaded in that time
+    effects: Vec<Effect>,
+    rollback_frame: Option<Frame>,
     local_handles: Vec<PlayerHandle>,
     last_checksum: (Frame, u64),
     periodic_checksum: (Frame, u64),
 }
 
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(
+        ruleset: Ruleset,
+        num_players: usize,
+    ) -> Result<Self, String> {
         let level = Level::new();
-        Self {
-            state: State::new(&level),
+        if level.player_starts.len() < num_players {
+            return Err(format!(
+                "the level only has starts for {} players",
+                level.player_starts.len()
+            ));
+        }
+        let hurtboxes = Hurtboxes::load()?;
+        return Ok(Self {
+            state: State::new(&level, num_players),
             level,
+            ruleset,
+            hurtboxes,
+            effects: Vec::new(),
+            rollback_frame: None,
             local_handles: Vec::new(),
             last_checksum: (NULL_FRAME, 0),
             periodic_checksum: (NULL_FRAME, 0),
-        }
+        });
     }
 
     // for each request, call the appropriate function
@@ -95,12 +172,27 @@ impl Game {
 
     pub fn advance_frame(&mut self, inputs: Vec<(Input, InputStatus)>) {
         //println!("advancing frame");
-        self.state.advance(inputs, &self.level);
+        self.state.advance(inputs, &self.ruleset, &self.hurtboxes);
+        self.effects.extend(self.state.effects.iter().cloned());
 
-        if self.state.round_end_frame != -1
-            && self.state.frame - self.state.round_end_frame > 60 * 5
-        {
-            self.state.reset();
+        let reset_delay =
+            if self.state.round_result == Some(RoundResult::Replay) {
+                60
+            } else {
+                60 * 5
+            };
+        if self.state.round_end_frame != -1 {
+            let frames_since_end =
+                self.state.frame - self.state.round_end_frame;
+            // close the curtain in time for the next round
+            if frames_since_end
+                == std::cmp::max(reset_delay - ROUND_END_FRAMES, 0)
+            {
+                self.state.close_curtain(&self.ruleset);
+            }
+            if frames_since_end > reset_delay {
+                self.state.reset(&self.level);
+            }
         }
 
         // remember checksum to render it later
@@ -130,6 +222,29 @@ impl Game {
     // load gamestate and overwrite
     fn load_game_state(&mut self, cell: GameStateCell<State>) {
         self.state = cell.load().expect("No data found.");
+        self.rollback_frame = Some(match self.rollback_frame {
+            Some(frame) => std::cmp::min(frame, self.state.frame),
+            None => self.state.frame,
+        });
+    }
+
+    pub fn take_effects(&mut self) -> (Option<Frame>, Vec<Effect>) {
+        return (
+            self.rollback_frame.take(),
+            std::mem::take(&mut self.effects),
+        );
+    }
+
+    // what both peers must agree on before a match can start
+    pub fn match_info(&self) -> MatchInfo {
+        return MatchInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            level_hash: self.level.hash(),
+            ruleset_hash: self.ruleset.hash(),
+            constants_hash: constants_hash(),
+            hurtboxes_hash: self.hurtboxes.hash(),
+            num_players: self.state.players.len(),
+        };
     }
 
     pub fn register_local_handles(&mut self, handles: Vec<PlayerHandle>) {
@@ -139,239 +254,301 @@ impl Game {
     pub fn local_input(
         &self,
         ctx: &mut Context,
-        _handle: PlayerHandle,
+        handle: PlayerHandle,
     ) -> Input {
+        // with several local players, each gets its own keys and gamepad
+        let source = self
+            .local_handles
+            .iter()
+            .position(|local_handle| *local_handle == handle)
+            .unwrap_or(0);
+        let keys = KEYBOARD_LAYOUTS.get(source);
+        let is_key_down = |get_key: fn(&KeyboardLayout) -> Key| {
+            return match keys {
+                Some(keys) => input::is_key_down(ctx, get_key(keys)),
+                None => false,
+            };
+        };
+        let gamepad = source;
         let mut inp: u8 = 0;
-        if input::is_key_down(ctx, Key::Up)
+        if is_key_down(|keys| keys.up)
             || input::get_gamepad_axis_position(
                 ctx,
-                0,
+                gamepad,
                 GamepadAxis::LeftStickY,
             ) < -0.5
         {
             inp |= INPUT_UP;
         }
-        if input::is_key_down(ctx, Key::Left)
+        if is_key_down(|keys| keys.left)
             || input::get_gamepad_axis_position(
                 ctx,
-                0,
+                gamepad,
                 GamepadAxis::LeftStickX,
             ) < -0.5
         {
             inp |= INPUT_LEFT;
         }
-        if input::is_key_down(ctx, Key::Down)
+        if is_key_down(|keys| keys.down)
             || input::get_gamepad_axis_position(
                 ctx,
-                0,
+                gamepad,
                 GamepadAxis::LeftStickY,
             ) > 0.5
         {
             inp |= INPUT_DOWN;
         }
-        if input::is_key_down(ctx, Key::Right)
+        if is_key_down(|keys| keys.right)
             || input::get_gamepad_axis_position(
                 ctx,
-                0,
+                gamepad,
                 GamepadAxis::LeftStickX,
             ) > 0.5
         {
             inp |= INPUT_RIGHT;
         }
-        if input::is_key_down(ctx, Key::Z)
-            || input::is_gamepad_button_down(ctx, 0, GamepadButton::A)
+        if is_key_down(|keys| keys.jump)
+            || input::is_gamepad_button_down(
+                ctx,
+                gamepad,
+                GamepadButton::A,
+            )
         {
             inp |= INPUT_JUMP;
         }
-        if input::is_key_down(ctx, Key::X)
-            || input::is_gamepad_button_down(ctx, 0, GamepadButton::X)
+        if is_key_down(|keys| keys.attack)
+            || input::is_gamepad_button_down(
+                ctx,
+                gamepad,
+                GamepadButton::X,
+            )
         {
             inp |= INPUT_ATTACK;
         }
-        if input::is_key_down(ctx, Key::C)
+        if is_key_down(|keys| keys.dodge)
             || input::get_gamepad_axis_position(
                 ctx,
-                0,
+                gamepad,
                 GamepadAxis::RightTrigger,
             ) > 0.5
         {
             inp |= INPUT_DODGE;
         }
-        Input { inp }
+        // quantized here so every peer simulates the same direction
+        let stick_x = input::get_gamepad_axis_position(
+            ctx,
+            gamepad,
+            GamepadAxis::LeftStickX,
+        );
+        let stick_y = input::get_gamepad_axis_position(
+            ctx,
+            gamepad,
+            GamepadAxis::LeftStickY,
+        );
+        let mut aim = NO_AIM;
+        if stick_x.hypot(stick_y) > AIM_DEADZONE {
+            aim = get_aim(stick_x, stick_y);
+        }
+        Input { inp, aim }
     }
 }
 
+// the aim closest to the direction of x and y
+pub fn get_aim(x: f32, y: f32) -> u8 {
+    let turns = y.atan2(x) / std::f32::consts::TAU;
+    let direction = (turns * AIM_DIRECTIONS as f32).round() as i32;
+    return direction.rem_euclid(AIM_DIRECTIONS as i32) as u8 + 1;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RoundResult {
+    Win { team: usize },
+    Draw,
+    // a trade under the sudden death trade rule
+    Replay,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct State {
     pub frame: i32,
-    pub prev_inputs: [u8; 2],
-    pub players: [Player; 2],
-    pub boomerangs: [Boomerang; 2],
+    // a copy of the level that sudden death can close in on
+    pub level: Level,
+    pub prev_inputs: Vec<u8>,
+    pub players: Vec<Player>,
+    pub boomerangs: Vec<Boomerang>,
+    // where everything was before the last frame, so rollback
+    // corrections can be drawn smoothly
+    pub prev_player_centers: Vec<IntVector2D>,
+    pub prev_boomerang_centers: Vec<IntVector2D>,
+    // counting from 1, including replayed rounds
+    pub round: i32,
     pub round_start_frame: i32,
     pub round_end_frame: i32,
-    #[serde(with = "BigArray")]
-    pub particles: [Particle; 100],
+    pub round_result: Option<RoundResult>,
+    // rounds won, indexed by team
+    pub scores: Vec<i32>,
+    pub is_sudden_death: bool,
+    pub sudden_death_frames: i32,
+    pub closed_rings: i32,
+    // frames left of the freeze after a kill
+    pub hitstop_frames: i32,
+    // in training, nobody runs out of lives and the round never ends
+    pub is_training: bool,
+    // cosmetic effects emitted this frame, played outside the rollback
+    // state
+    pub effects: Vec<Effect>,
     pub curtain: Curtain,
 }
 
 impl State {
-    pub fn new(level: &Level) -> Self {
-        let player_one = Player::new(
-            level.player_starts.0.x,
-            level.player_starts.0.y - 1,
-            false,
-        );
-        let mut player_two = Player::new(
-            level.player_starts.1.x,
-            level.player_starts.1.y - 1,
-            true,
-        );
-        player_two.is_facing_left = true;
-        let particles = [
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-            Particle::new(),
-        ];
-        Self {
+    pub fn new(level: &Level, num_players: usize) -> Self {
+        // every other player starts on the right, facing left
+        let players = level.player_starts[0..num_players]
+            .iter()
+            .enumerate()
+            .map(|(player_num, start)| {
+                Player::new(start.x, start.y - 1, player_num % 2 == 1)
+            })
+            .collect();
+        let mut state = Self {
             frame: 0,
-            prev_inputs: [0, 0],
-            players: [player_one, player_two],
-            boomerangs: [Boomerang::new(), Boomerang::new()],
+            level: level.clone(),
+            prev_inputs: vec![0; num_players],
+            players,
+            boomerangs: vec![Boomerang::new(); num_players],
+            prev_player_centers: Vec::new(),
+            prev_boomerang_centers: Vec::new(),
+            round: 1,
             round_start_frame: 0,
             round_end_frame: -1,
-            particles,
+            round_result: None,
+            scores: vec![0; num_players],
+            is_sudden_death: false,
+            sudden_death_frames: 0,
+            closed_rings: 0,
+            hitstop_frames: 0,
+            is_training: false,
+            effects: Vec::new(),
             curtain: Curtain::new(),
-        }
+        };
+        state.remember_centers();
+        return state;
     }
 
-    pub fn reset(&mut self) {
+    pub fn reset(&mut self, level: &Level) {
         println!("resetting");
-        let player_one = Player::new(
-            self.players[0].start.x,
-            self.players[0].start.y,
-            false,
-        );
-        let player_two = Player::new(
-            self.players[1].start.x,
-            self.players[1].start.y,
-            false,
-        );
-        self.prev_inputs = [0, 0];
-        self.players = [player_one, player_two];
-        self.boomerangs = [Boomerang::new(), Boomerang::new()];
+        self.level = level.clone();
+        self.closed_rings = 0;
+        self.sudden_death_frames = 0;
+        self.hitstop_frames = 0;
+        let num_players = self.players.len();
+        self.prev_inputs = vec![0; num_players];
+        self.players = self
+            .players
+            .iter()
+            .map(|player| {
+                Player::new(player.start.x, player.start.y, false)
+            })
+            .collect();
+        self.boomerangs = vec![Boomerang::new(); num_players];
+        self.round += 1;
         self.round_start_frame = self.frame;
         self.round_end_frame = -1;
+        self.is_sudden_death =
+            self.round_result == Some(RoundResult::Replay);
+        self.round_result = None;
+        self.curtain.open(Transition::Wipe, WIPE_FRAMES);
+    }
+
+    // puts every player back at the given spot, as if they'd just
+    // spawned there, without starting a new round
+    pub fn place_players(&mut self, spots: &[IntVector2D]) {
+        self.hitstop_frames = 0;
+        self.prev_inputs = vec![0; self.players.len()];
+        for (player_num, spot) in spots.iter().enumerate() {
+            let is_facing_left =
+                spot.x > self.level.width_in_tiles * TILE_SIZE / 2;
+            let mut player = Player::new(spot.x, spot.y, is_facing_left);
+            player.start = self.players[player_num].start;
+            self.players[player_num] = player;
+            self.boomerangs[player_num] = Boomerang::new();
+        }
+    }
+
+    fn remember_centers(&mut self) {
+        self.prev_player_centers = self
+            .players
+            .iter()
+            .map(|player| IntVector2D {
+                x: player.center_x(),
+                y: player.center_y(),
+            })
+            .collect();
+        self.prev_boomerang_centers = self
+            .boomerangs
+            .iter()
+            .map(|boomerang| IntVector2D {
+                x: boomerang.center_x(),
+                y: boomerang.center_y(),
+            })
+            .collect();
+    }
+
+    // closes in on the winner, or fades out if nobody won
+    pub fn close_curtain(&mut self, ruleset: &Ruleset) {
+        let winner = match self.round_result {
+            Some(RoundResult::Win { team }) => (0..self.players.len())
+                .find(|player_num| {
+                    ruleset.team(*player_num) == team
+                        && !self.players[*player_num].is_out()
+                }),
+            _ => None,
+        };
+        match winner {
+            Some(winner) => {
+                self.curtain.center = IntVector2D {
+                    x: self.players[winner].center_x(),
+                    y: self.players[winner].center_y(),
+                };
+                self.curtain.close(Transition::Iris, ROUND_END_FRAMES);
+            }
+            None => self.curtain.close(Transition::Fade, ROUND_END_FRAMES),
+        }
     }
 
     pub fn advance(
         &mut self,
         inputs: Vec<(Input, InputStatus)>,
-        level: &Level,
+        ruleset: &Ruleset,
+        hurtboxes: &Hurtboxes,
     ) {
         self.frame += 1;
+        self.effects.clear();
+        self.remember_centers();
+        let num_players = self.players.len();
 
         // update curtain
         self.curtain.advance();
 
+        // the world stands still during hitstop. the round clock is
+        // pushed back so it doesn't run, and inputs aren't remembered so
+        // a button pressed during the freeze still counts afterwards
+        if self.hitstop_frames > 0 {
+            self.hitstop_frames -= 1;
+            self.round_start_frame += 1;
+            if self.round_end_frame != -1 {
+                self.round_end_frame += 1;
+            }
+            return;
+        }
+
+        self.advance_sudden_death(ruleset);
+        let boomerang_speedup = self.get_boomerang_speedup(ruleset);
+
+        self.advance_respawns(ruleset);
+
         // update players
-        for player_num in 0..2 {
+        for player_num in 0..num_players {
             if self.players[player_num].is_dead {
                 self.players[player_num]
                     .add_sound_command("run", "stop", 100);
@@ -383,142 +560,916 @@ impl State {
                 continue;
             }
             let input = inputs[player_num].0.inp;
-            let other_player_hitbox =
-                &self.players[1 - player_num].hitbox.clone();
-            let other_boomerang_hitbox =
-                &self.boomerangs[1 - player_num].hitbox.clone();
+            let aim = inputs[player_num].0.aim;
+            let opponents = self.get_opponents(player_num, ruleset);
+            let opponent_hurtboxes = self.get_player_hurtboxes(&opponents);
+            let opponent_boomerang_hitboxes =
+                self.get_boomerang_hitboxes(&opponents);
             self.players[player_num].advance(
                 input,
                 self.prev_inputs[player_num],
-                level,
-                other_player_hitbox,
-                other_boomerang_hitbox,
+                aim,
+                &self.level,
+                ruleset,
+                hurtboxes,
+                &opponent_hurtboxes,
+                &opponent_boomerang_hitboxes,
             );
         }
 
         // update boomerangs
-        for player_num in 0..2 {
+        for player_num in 0..num_players {
             if self.players[player_num].is_dead {
                 self.boomerangs[player_num]
                     .add_sound_command("whoosh", "stop", 100);
                 continue;
             }
             let input = inputs[player_num].0.inp;
-            let other_player_hitbox =
-                &self.players[1 - player_num].hitbox.clone();
+            let aim = inputs[player_num].0.aim;
+            let targets = if self.boomerangs[player_num].is_reflected {
+                self.get_teammates(player_num, ruleset)
+            } else {
+                self.get_opponents(player_num, ruleset)
+            };
+            let target_hurtboxes = self.get_player_hurtboxes(&targets);
             self.boomerangs[player_num].advance(
                 input,
                 self.prev_inputs[player_num],
+                aim,
                 &self.players[player_num],
-                other_player_hitbox,
+                &target_hurtboxes,
+                &self.level,
+                ruleset,
+                boomerang_speedup,
             );
         }
 
-        // update particles
-        for player_num in 0..2 {
-            //for particle_spawn in self.players[player_num].particle_spawns {
-            for _ in 0..self.players[player_num].particle_spawns.len() {
-                let particle_spawn = self.players[player_num]
-                    .particle_spawns
-                    .pop()
-                    .unwrap();
-                let particle_num = self.get_free_particle_index();
-                self.particles[particle_num].position.x =
-                    particle_spawn.0.x;
-                self.particles[particle_num].position.y =
-                    particle_spawn.0.y;
-                self.particles[particle_num]
-                    .set_animation(&particle_spawn.1);
+        // pass particle spawns on as effects
+        for player_num in 0..num_players {
+            let spawns: Vec<(IntVector2D, String)> = self.players
+                [player_num]
+                .particle_spawns
+                .drain(..)
+                .chain(
+                    self.boomerangs[player_num].particle_spawns.drain(..),
+                )
+                .collect();
+            for (position, emitter) in spawns {
+                self.add_effect(&emitter, position);
             }
         }
-        for particle_num in 0..self.particles.len() {
-            self.particles[particle_num].advance();
-        }
 
         // combat interactions
-        for player_num in 0..2 {
+        for player_num in 0..num_players {
             if self.players[player_num].is_dead {
                 continue;
             }
-            if self.boomerangs[player_num].collided_with_player {
-                self.players[1 - player_num].collided_with_boomerang =
-                    true;
+            for victim in
+                self.boomerangs[player_num].collided_with_players.clone()
+            {
+                let victim_boomerangs =
+                    &mut self.players[victim].collided_with_boomerangs;
+                if !victim_boomerangs.contains(&player_num) {
+                    victim_boomerangs.push(player_num);
+                }
             }
         }
-        for player_num in 0..2 {
-            if self.players[player_num].is_dead {
+
+        self.resolve_boomerang_interactions(ruleset);
+
+        self.resolve_deaths(ruleset);
+
+        // set previous inputs
+        for player_num in 0..num_players {
+            let input = inputs[player_num].0.inp;
+            self.prev_inputs[player_num] = input;
+        }
+    }
+
+    // parries, then clashes, each in player order so every peer resolves
+    // them alike. a boomerang parried on the same frame as it touches
+    // another still clashes with it
+    fn resolve_boomerang_interactions(&mut self, ruleset: &Ruleset) {
+        let num_players = self.players.len();
+        for player_num in 0..num_players {
+            if self.players[player_num].is_dead
+                || !self.players[player_num].is_parrying(ruleset)
+            {
                 continue;
             }
-            if self.players[player_num].collided_with_player
-                && self.players[player_num].dodge_timer == 0
-                && self.players[1 - player_num].dodge_timer > 0
+            for owner in
+                self.players[player_num].collided_with_boomerangs.clone()
             {
-                self.players[player_num].will_die = true;
+                if self.boomerangs[owner].is_reflected
+                    || !self.can_boomerang_hurt(owner, player_num, ruleset)
+                {
+                    continue;
+                }
+                self.boomerangs[owner].reflect();
+                self.players[player_num]
+                    .add_sound_command("parry", "play", 100);
+                let position = IntVector2D {
+                    x: self.boomerangs[owner].center_x(),
+                    y: self.boomerangs[owner].center_y(),
+                };
+                self.spawn_sparks(position);
             }
+        }
+        for first in 0..num_players {
+            for second in (first + 1)..num_players {
+                if !ruleset.can_hurt(first, second)
+                    || !self.boomerangs[first].is_dangerous()
+                    || !self.boomerangs[second].is_dangerous()
+                    || !self.boomerangs[first].is_in_flight()
+                    || !self.boomerangs[second].is_in_flight()
+                    || !do_hitboxes_overlap(
+                        &self.boomerangs[first].hitbox,
+                        &self.boomerangs[second].hitbox,
+                    )
+                {
+                    continue;
+                }
+                // two boomerangs already heading home pass each other
+                if self.boomerangs[first].is_returning
+                    && self.boomerangs[second].is_returning
+                {
+                    continue;
+                }
+                self.boomerangs[first].deflect();
+                self.boomerangs[second].deflect();
+                self.boomerangs[first]
+                    .add_sound_command("clash", "play", 100);
+                let position = IntVector2D {
+                    x: (self.boomerangs[first].center_x()
+                        + self.boomerangs[second].center_x())
+                        / 2,
+                    y: (self.boomerangs[first].center_y()
+                        + self.boomerangs[second].center_y())
+                        / 2,
+                };
+                self.spawn_sparks(position);
+            }
+        }
+    }
 
-            if self.players[player_num].collided_with_boomerang
+    // every death is decided before any is applied, so players who kill
+    // each other on the same frame both die whatever their order
+    fn resolve_deaths(&mut self, ruleset: &Ruleset) {
+        let num_players = self.players.len();
+        let teams_alive_before = self.get_teams_alive(ruleset);
+        // body contact is found while each player moves, so only whoever
+        // moved later sees the other's new position. count it both ways
+        // so it doesn't depend on player order
+        for player_num in 0..num_players {
+            for other in
+                self.players[player_num].collided_with_players.clone()
+            {
+                let other_players =
+                    &mut self.players[other].collided_with_players;
+                if !other_players.contains(&player_num) {
+                    other_players.push(player_num);
+                }
+            }
+        }
+        let mut kinds: Vec<&str> = vec!["kill_crush"; num_players];
+        for player_num in 0..num_players {
+            if self.players[player_num].is_dead {
+                continue;
+            }
+            let is_hit_by_player = self.players[player_num]
+                .collided_with_players
+                .iter()
+                .any(|other| self.players[*other].dodge_timer > 0);
+            let is_hit_by_boomerang = self.players[player_num]
+                .collided_with_boomerangs
+                .iter()
+                .any(|owner| {
+                    self.can_boomerang_hurt(*owner, player_num, ruleset)
+                });
+            if (is_hit_by_player || is_hit_by_boomerang)
                 && self.players[player_num].dodge_timer == 0
-                && !self.boomerangs[1 - player_num].is_holstered
+                && self.players[player_num].invulnerable_timer == 0
             {
+                // a crush from sudden death counts over anything else
+                if !self.players[player_num].will_die {
+                    kinds[player_num] = if is_hit_by_boomerang {
+                        "kill_boomerang"
+                    } else {
+                        "kill_dodge"
+                    };
+                }
                 self.players[player_num].will_die = true;
             }
         }
 
         // kill players
-        for player_num in 0..2 {
+        for player_num in 0..num_players {
             if self.players[player_num].is_dead {
                 continue;
             }
             if self.players[player_num].will_die {
                 self.players[player_num].will_die = false;
                 self.players[player_num].is_dead = true;
+                if !self.is_training {
+                    self.players[player_num].stocks_lost += 1;
+                }
+                if self.players[player_num].stocks_lost < ruleset.stocks {
+                    self.players[player_num].respawn_timer =
+                        std::cmp::max(ruleset.respawn_delay, 1);
+                }
                 self.boomerangs[player_num].is_holstered = true;
-                self.round_end_frame = self.frame;
                 self.players[player_num]
                     .add_sound_command("death", "play", 100);
 
-                // Create explosion
-                let values = [-10, -5, 0, 5, 10];
-                let mut angles = [IntVector2D { x: 0, y: 0 }; 25];
-                for x_val in 0..values.len() {
-                    for y_val in 0..values.len() {
-                        let angle_num = x_val * values.len() + y_val;
-                        angles[angle_num].x = values[x_val];
-                        angles[angle_num].y = values[y_val];
-                        angles[angle_num].normalize(9000);
-                    }
-                }
-                for angle in angles {
-                    if angle.x == 0 && angle.y == 0 {
-                        continue;
+                let position = IntVector2D {
+                    x: self.players[player_num].center_x(),
+                    y: self.players[player_num].center_y(),
+                };
+                self.add_effect("explosion", position);
+                self.add_effect(kinds[player_num], position);
+                self.hitstop_frames = ruleset.hitstop;
+            }
+        }
+
+        // the round is over once at most one team is left standing
+        if self.round_end_frame != -1 || self.is_training {
+            return;
+        }
+        let teams_alive = self.get_teams_alive(ruleset);
+        if teams_alive.len() == 1 {
+            self.scores[teams_alive[0]] += 1;
+            self.round_result = Some(RoundResult::Win {
+                team: teams_alive[0],
+            });
+        } else if teams_alive.is_empty() {
+            // the last teams standing traded
+            self.round_result = Some(match ruleset.trade_rule {
+                TradeRule::Draw => RoundResult::Draw,
+                TradeRule::BothScore => {
+                    for team in teams_alive_before {
+                        self.scores[team] += 1;
                     }
-                    let particle_num = self.get_free_particle_index();
-                    self.particles[particle_num].position.x =
-                        self.players[player_num].center_x();
-                    self.particles[particle_num].position.y =
-                        self.players[player_num].center_y();
-                    self.particles[particle_num].set_animation("simple");
-                    self.particles[particle_num].velocity.x = angle.x;
-                    self.particles[particle_num].velocity.y = angle.y;
+                    RoundResult::Draw
                 }
+                TradeRule::SuddenDeath => RoundResult::Replay,
+            });
+        } else {
+            return;
+        }
+        self.round_end_frame = self.frame;
+    }
+
+    // the living players that this player can hurt and be hurt by
+    pub fn get_opponents(
+        &self,
+        player_num: usize,
+        ruleset: &Ruleset,
+    ) -> Vec<usize> {
+        return (0..self.players.len())
+            .filter(|other| {
+                !self.players[*other].is_dead
+                    && ruleset.can_hurt(player_num, *other)
+            })
+            .collect();
+    }
+
+    // the living players on this player's team, including them
+    fn get_teammates(
+        &self,
+        player_num: usize,
+        ruleset: &Ruleset,
+    ) -> Vec<usize> {
+        return (0..self.players.len())
+            .filter(|other| {
+                !self.players[*other].is_dead
+                    && ruleset.team(*other) == ruleset.team(player_num)
+            })
+            .collect();
+    }
+
+    // a reflected boomerang only hurts its thrower's team, and any other
+    // only hurts who its thrower could
+    fn can_boomerang_hurt(
+        &self,
+        owner: usize,
+        victim: usize,
+        ruleset: &Ruleset,
+    ) -> bool {
+        let boomerang = &self.boomerangs[owner];
+        if !boomerang.is_dangerous() {
+            return false;
+        }
+        if boomerang.is_reflected {
+            return ruleset.team(owner) == ruleset.team(victim);
+        }
+        return ruleset.can_hurt(owner, victim);
+    }
+
+    fn get_player_hurtboxes(
+        &self,
+        player_nums: &[usize],
+    ) -> Vec<(usize, Hitbox)> {
+        return player_nums
+            .iter()
+            .map(|player_num| {
+                (*player_num, self.players[*player_num].hurtbox())
+            })
+            .collect();
+    }
+
+    fn get_boomerang_hitboxes(
+        &self,
+        player_nums: &[usize],
+    ) -> Vec<(usize, Hitbox)> {
+        return player_nums
+            .iter()
+            .map(|player_num| {
+                (*player_num, self.boomerangs[*player_num].hitbox.clone())
+            })
+            .collect();
+    }
+
+    // frames left before sudden death, or 0 once it has started
+    pub fn get_round_time_left(&self, ruleset: &Ruleset) -> i32 {
+        if self.is_sudden_death {
+            return 0;
+        }
+        let elapsed = self.frame - self.round_start_frame;
+        return std::cmp::max(ruleset.round_time - elapsed, 0);
+    }
+
+    fn advance_sudden_death(&mut self, ruleset: &Ruleset) {
+        if self.round_end_frame != -1 || self.is_training {
+            return;
+        }
+        if !self.is_sudden_death {
+            if ruleset.round_time == 0
+                || self.get_round_time_left(ruleset) > 0
+            {
+                return;
+            }
+            self.is_sudden_death = true;
+        }
+        self.sudden_death_frames += 1;
+        if ruleset.sudden_death != SuddenDeath::CloseIn
+            || self.sudden_death_frames
+                % std::cmp::max(ruleset.sudden_death_close_interval, 1)
+                != 0
+            || !self.level.close_ring(self.closed_rings)
+        {
+            return;
+        }
+        self.closed_rings += 1;
+        // anyone caught in the new ring is crushed
+        for player in self.players.iter_mut() {
+            if !player.is_dead && self.level.collide(&player.hitbox) {
+                player.will_die = true;
             }
         }
+    }
 
-        // set previous inputs
-        for player_num in 0..2 {
-            let input = inputs[player_num].0.inp;
-            self.prev_inputs[player_num] = input;
+    fn get_boomerang_speedup(&self, ruleset: &Ruleset) -> i32 {
+        if !self.is_sudden_death
+            || ruleset.sudden_death != SuddenDeath::SpeedUp
+        {
+            return 0;
+        }
+        return std::cmp::min(
+            self.sudden_death_frames * ruleset.sudden_death_speedup
+                / OG_FPS,
+            ruleset.sudden_death_max_speedup,
+        );
+    }
+
+    fn advance_respawns(&mut self, ruleset: &Ruleset) {
+        for player_num in 0..self.players.len() {
+            if !self.players[player_num].is_dead
+                || self.players[player_num].respawn_timer == 0
+            {
+                continue;
+            }
+            self.players[player_num].respawn_timer -= 1;
+            if self.players[player_num].respawn_timer > 0
+                || self.round_end_frame != -1
+            {
+                continue;
+            }
+            let old_player = &self.players[player_num];
+            let point = self.get_respawn_point(player_num, ruleset);
+            let is_facing_left =
+                point.x > self.level.width_in_tiles * TILE_SIZE / 2;
+            let mut player =
+                Player::new(point.x, point.y - 1, is_facing_left);
+            player.start = old_player.start;
+            player.stocks_lost = old_player.stocks_lost;
+            player.invulnerable_timer = ruleset.respawn_invulnerability;
+            player.add_sound_command("ready", "play", 100);
+            self.players[player_num] = player;
+            self.boomerangs[player_num] = Boomerang::new();
+        }
+    }
+
+    // the start farthest from the nearest opponent, skipping any that
+    // sudden death has filled in. ties go to the first start, so every
+    // peer picks the same one
+    fn get_respawn_point(
+        &self,
+        player_num: usize,
+        ruleset: &Ruleset,
+    ) -> IntVector2D {
+        let opponents: Vec<usize> = self
+            .get_opponents(player_num, ruleset)
+            .into_iter()
+            .filter(|other| *other != player_num)
+            .collect();
+        let mut best = self.players[player_num].start;
+        let mut best_distance = -1;
+        for start in &self.level.player_starts {
+            let hitbox = Hitbox {
+                x: start.x,
+                y: start.y - 1,
+                width: self.players[player_num].hitbox.width,
+                height: self.players[player_num].hitbox.height,
+            };
+            if self.level.collide(&hitbox) {
+                continue;
+            }
+            let distance = opponents
+                .iter()
+                .map(|other| {
+                    let dx = (self.players[*other].center_x()
+                        - (hitbox.x + hitbox.width / 2))
+                        as i64;
+                    let dy = (self.players[*other].center_y()
+                        - (hitbox.y + hitbox.height / 2))
+                        as i64;
+                    dx * dx + dy * dy
+                })
+                .min()
+                .unwrap_or(0);
+            if distance > best_distance {
+                best = *start;
+                best_distance = distance;
+            }
         }
+        return best;
     }
 
-    pub fn get_free_particle_index(&mut self) -> usize {
-        for particle_num in 0..self.particles.len() {
-            if self.particles[particle_num].current_animation
-                == "none".to_string()
+    pub fn get_teams_alive(&self, ruleset: &Ruleset) -> Vec<usize> {
+        let mut teams = Vec::new();
+        for player_num in 0..self.players.len() {
+            let team = ruleset.team(player_num);
+            if !self.players[player_num].is_out() && !teams.contains(&team)
             {
-                return particle_num as usize;
+                teams.push(team);
             }
         }
-        return 0;
+        return teams;
+    }
+
+    fn spawn_sparks(&mut self, position: IntVector2D) {
+        self.add_effect("sparks", position);
+    }
+
+    fn add_effect(&mut self, emitter: &str, position: IntVector2D) {
+        self.effects.push(Effect {
+            frame: self.frame,
+            emitter: emitter.to_string(),
+            position,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what one player does to the other on the frame being resolved
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Attack {
+        Nothing,
+        Boomerang,
+        Dodge,
+    }
+
+    const ATTACKS: [Attack; 3] =
+        [Attack::Nothing, Attack::Boomerang, Attack::Dodge];
+
+    fn attack(
+        state: &mut State,
+        attacker: usize,
+        victim: usize,
+        kind: Attack,
+    ) {
+        match kind {
+            Attack::Nothing => {}
+            Attack::Boomerang => {
+                state.boomerangs[attacker].is_holstered = false;
+                state.players[victim]
+                    .collided_with_boomerangs
+                    .push(attacker);
+            }
+            // only the dodging player saw the contact, as if they moved
+            // second
+            Attack::Dodge => {
+                state.players[attacker].dodge_timer = 5;
+                state.players[attacker].collided_with_players.push(victim);
+            }
+        }
+    }
+
+    // player 0 does first to player 1 while player 1 does second to
+    // player 0
+    fn resolve(first: Attack, second: Attack, ruleset: &Ruleset) -> State {
+        let mut state = State::new(&Level::new(), 2);
+        attack(&mut state, 0, 1, first);
+        attack(&mut state, 1, 0, second);
+        state.resolve_deaths(ruleset);
+        return state;
+    }
+
+    // a dodging player can't be hurt, by a boomerang or another dodge
+    fn kills(attack: Attack, defense: Attack) -> bool {
+        return attack != Attack::Nothing && defense != Attack::Dodge;
+    }
+
+    #[test]
+    fn every_combination_resolves_the_same_whatever_the_order() {
+        let ruleset = Ruleset::default();
+        for first in ATTACKS {
+            for second in ATTACKS {
+                let state = resolve(first, second, &ruleset);
+                let mirrored = resolve(second, first, &ruleset);
+                let label = format!("{:?} against {:?}", first, second);
+                assert_eq!(
+                    state.players[1].is_dead,
+                    kills(first, second),
+                    "{}",
+                    label
+                );
+                assert_eq!(
+                    state.players[0].is_dead,
+                    kills(second, first),
+                    "{}",
+                    label
+                );
+                assert_eq!(
+                    state.players[0].is_dead, mirrored.players[1].is_dead,
+                    "{}",
+                    label
+                );
+                assert_eq!(
+                    state.players[1].is_dead, mirrored.players[0].is_dead,
+                    "{}",
+                    label
+                );
+                let expected = match (
+                    state.players[0].is_dead,
+                    state.players[1].is_dead,
+                ) {
+                    (false, false) => None,
+                    (false, true) => Some(RoundResult::Win { team: 0 }),
+                    (true, false) => Some(RoundResult::Win { team: 1 }),
+                    (true, true) => Some(RoundResult::Draw),
+                };
+                assert_eq!(state.round_result, expected, "{}", label);
+            }
+        }
+    }
+
+    #[test]
+    fn a_win_scores_for_the_winner() {
+        let ruleset = Ruleset::default();
+        let state = resolve(Attack::Nothing, Attack::Dodge, &ruleset);
+        assert_eq!(state.round_result, Some(RoundResult::Win { team: 1 }));
+        assert_eq!(state.scores, vec![0, 1]);
+        assert_ne!(state.round_end_frame, -1);
+    }
+
+    #[test]
+    fn a_trade_is_a_draw() {
+        let ruleset = Ruleset {
+            trade_rule: TradeRule::Draw,
+            ..Ruleset::default()
+        };
+        let state =
+            resolve(Attack::Boomerang, Attack::Boomerang, &ruleset);
+        assert_eq!(state.round_result, Some(RoundResult::Draw));
+        assert_eq!(state.scores, vec![0, 0]);
+    }
+
+    #[test]
+    fn a_trade_can_score_for_both() {
+        let ruleset = Ruleset {
+            trade_rule: TradeRule::BothScore,
+            ..Ruleset::default()
+        };
+        let state =
+            resolve(Attack::Boomerang, Attack::Boomerang, &ruleset);
+        assert_eq!(state.round_result, Some(RoundResult::Draw));
+        assert_eq!(state.scores, vec![1, 1]);
+    }
+
+    #[test]
+    fn a_trade_can_replay_as_sudden_death() {
+        let ruleset = Ruleset {
+            trade_rule: TradeRule::SuddenDeath,
+            ..Ruleset::default()
+        };
+        let mut state =
+            resolve(Attack::Boomerang, Attack::Boomerang, &ruleset);
+        assert_eq!(state.round_result, Some(RoundResult::Replay));
+        assert_eq!(state.scores, vec![0, 0]);
+        state.reset(&Level::new());
+        assert!(state.is_sudden_death);
+        assert_eq!(state.round_result, None);
+        assert!(state.players.iter().all(|player| !player.is_dead));
+    }
+
+    fn parry_ruleset() -> Ruleset {
+        return Ruleset {
+            parry_window: 4,
+            ..Ruleset::default()
+        };
+    }
+
+    // the parrier has just started dodging into the thrower's
+    // boomerang, which is flying toward them
+    fn parry(parrier: usize, thrower: usize) -> State {
+        let mut state = State::new(&Level::new(), 2);
+        state.players[parrier].reset_dodge_timer(10);
+        state.players[parrier]
+            .collided_with_boomerangs
+            .push(thrower);
+        state.boomerangs[thrower].is_holstered = false;
+        state.boomerangs[thrower].velocity = IntVector2D { x: 5000, y: 0 };
+        assert!(state.players[parrier].is_parrying(&parry_ruleset()));
+        return state;
+    }
+
+    #[test]
+    fn classic_has_no_parries() {
+        let state = parry(0, 1);
+        assert!(!state.players[0].is_parrying(&Ruleset::default()));
+    }
+
+    #[test]
+    fn a_parry_sends_the_boomerang_back_at_its_thrower() {
+        let ruleset = parry_ruleset();
+        let mut state = parry(0, 1);
+        state.resolve_boomerang_interactions(&ruleset);
+        assert!(state.boomerangs[1].is_reflected);
+        assert_eq!(state.boomerangs[1].velocity.x, -5000);
+        assert!(state.can_boomerang_hurt(1, 1, &ruleset));
+        assert!(!state.can_boomerang_hurt(1, 0, &ruleset));
+
+        // the parrier touching it again on later frames does nothing
+        state.resolve_boomerang_interactions(&ruleset);
+        assert_eq!(state.boomerangs[1].velocity.x, -5000);
+    }
+
+    #[test]
+    fn a_parry_and_a_clash_on_one_frame_resolve_alike() {
+        let ruleset = parry_ruleset();
+        let mut outcomes = Vec::new();
+        for (parrier, thrower) in [(0, 1), (1, 0)] {
+            // the parrier's own boomerang is out too, touching the
+            // thrower's
+            let mut state = parry(parrier, thrower);
+            state.boomerangs[parrier].is_holstered = false;
+            state.resolve_boomerang_interactions(&ruleset);
+            outcomes.push([
+                state.boomerangs[parrier].is_deflected,
+                state.boomerangs[parrier].is_reflected,
+                state.boomerangs[thrower].is_deflected,
+                state.boomerangs[thrower].is_reflected,
+            ]);
+            assert!(!state.can_boomerang_hurt(thrower, thrower, &ruleset));
+            assert!(!state.can_boomerang_hurt(parrier, thrower, &ruleset));
+        }
+        // the parry happens first, then the clash knocks both away
+        assert_eq!(outcomes[0], [true, false, true, false]);
+        assert_eq!(outcomes[0], outcomes[1]);
+    }
+
+    #[test]
+    fn a_team_trade_scores_only_the_teams_still_standing() {
+        let ruleset = Ruleset {
+            teams: true,
+            trade_rule: TradeRule::BothScore,
+            ..Ruleset::default()
+        };
+        let mut state = State::new(&Level::new(), 4);
+        state.players[2].is_dead = true;
+        attack(&mut state, 1, 0, Attack::Boomerang);
+        attack(&mut state, 3, 0, Attack::Boomerang);
+        attack(&mut state, 0, 1, Attack::Boomerang);
+        attack(&mut state, 0, 3, Attack::Boomerang);
+        state.resolve_deaths(&ruleset);
+        assert!(state.players.iter().all(|player| player.is_dead));
+        assert_eq!(state.round_result, Some(RoundResult::Draw));
+        assert_eq!(state.scores, vec![1, 1, 0, 0]);
+    }
+
+    fn timer_ruleset(sudden_death: SuddenDeath) -> Ruleset {
+        return Ruleset {
+            round_time: 100,
+            sudden_death,
+            sudden_death_speedup: 20 * 1000,
+            sudden_death_max_speedup: 30 * 1000,
+            sudden_death_close_interval: 10,
+            ..Ruleset::default()
+        };
+    }
+
+    #[test]
+    fn classic_rounds_never_run_out_of_time() {
+        let ruleset = Ruleset::default();
+        let mut state = State::new(&Level::new(), 2);
+        state.frame = 60 * 60 * 10;
+        state.advance_sudden_death(&ruleset);
+        assert!(!state.is_sudden_death);
+    }
+
+    #[test]
+    fn sudden_death_starts_when_the_round_time_is_up() {
+        let ruleset = timer_ruleset(SuddenDeath::CloseIn);
+        let mut state = State::new(&Level::new(), 2);
+        state.round_start_frame = 50;
+        state.frame = 149;
+        state.advance_sudden_death(&ruleset);
+        assert_eq!(state.get_round_time_left(&ruleset), 1);
+        assert!(!state.is_sudden_death);
+
+        state.frame = 150;
+        state.advance_sudden_death(&ruleset);
+        assert!(state.is_sudden_death);
+        assert_eq!(state.get_round_time_left(&ruleset), 0);
+    }
+
+    #[test]
+    fn closing_in_fills_a_ring_every_interval() {
+        let ruleset = timer_ruleset(SuddenDeath::CloseIn);
+        let mut state = State::new(&Level::new(), 2);
+        state.frame = ruleset.round_time;
+        // the level's edges are already solid, so the first ring to
+        // change anything is the second
+        let inner_corner = (state.level.width_in_tiles + 1) as usize;
+        for _ in 0..19 {
+            state.advance_sudden_death(&ruleset);
+        }
+        assert_eq!(state.closed_rings, 1);
+        assert!(!state.level.grid[inner_corner]);
+
+        state.advance_sudden_death(&ruleset);
+        assert_eq!(state.closed_rings, 2);
+        assert!(state.level.grid[inner_corner]);
+        assert_eq!(state.get_boomerang_speedup(&ruleset), 0);
+    }
+
+    #[test]
+    fn speeding_up_is_capped() {
+        let ruleset = timer_ruleset(SuddenDeath::SpeedUp);
+        let mut state = State::new(&Level::new(), 2);
+        state.frame = ruleset.round_time;
+        for _ in 0..OG_FPS {
+            state.advance_sudden_death(&ruleset);
+        }
+        assert_eq!(state.get_boomerang_speedup(&ruleset), 20 * 1000);
+        assert_eq!(state.closed_rings, 0);
+
+        for _ in 0..OG_FPS {
+            state.advance_sudden_death(&ruleset);
+        }
+        assert_eq!(state.get_boomerang_speedup(&ruleset), 30 * 1000);
+    }
+
+    fn stock_ruleset() -> Ruleset {
+        return Ruleset {
+            stocks: 3,
+            respawn_delay: 10,
+            respawn_invulnerability: 20,
+            ..Ruleset::default()
+        };
+    }
+
+    #[test]
+    fn classic_players_are_out_after_one_hit() {
+        let state = resolve(
+            Attack::Boomerang,
+            Attack::Nothing,
+            &Ruleset::default(),
+        );
+        assert!(state.players[1].is_out());
+        assert_eq!(state.round_result, Some(RoundResult::Win { team: 0 }));
+    }
+
+    #[test]
+    fn players_with_stocks_left_respawn() {
+        let ruleset = stock_ruleset();
+        let mut state =
+            resolve(Attack::Boomerang, Attack::Nothing, &ruleset);
+        assert!(state.players[1].is_dead);
+        assert!(!state.players[1].is_out());
+        assert_eq!(state.round_result, None);
+        assert_eq!(state.round_end_frame, -1);
+
+        for _ in 0..9 {
+            state.advance_respawns(&ruleset);
+        }
+        assert!(state.players[1].is_dead);
+        state.advance_respawns(&ruleset);
+        assert!(!state.players[1].is_dead);
+        assert_eq!(state.players[1].stocks_lost, 1);
+        assert_eq!(state.players[1].invulnerable_timer, 20);
+
+        // invulnerable players shrug off hits
+        attack(&mut state, 0, 1, Attack::Boomerang);
+        state.resolve_deaths(&ruleset);
+        assert!(!state.players[1].is_dead);
+    }
+
+    #[test]
+    fn the_last_stock_puts_a_player_out() {
+        let ruleset = stock_ruleset();
+        let mut state = State::new(&Level::new(), 2);
+        state.players[1].stocks_lost = 2;
+        attack(&mut state, 0, 1, Attack::Boomerang);
+        state.resolve_deaths(&ruleset);
+        assert!(state.players[1].is_out());
+        assert_eq!(state.round_result, Some(RoundResult::Win { team: 0 }));
+    }
+
+    #[test]
+    fn players_respawn_at_the_open_start_farthest_from_opponents() {
+        let ruleset = stock_ruleset();
+        let mut state = State::new(&Level::new(), 2);
+        let starts = state.level.player_starts.clone();
+        state.players[1].hitbox.x = starts[0].x;
+        state.players[1].hitbox.y = starts[0].y - 1;
+        assert_eq!(state.get_respawn_point(0, &ruleset), starts[1]);
+
+        // sudden death filled in the farthest start
+        let tile_x = starts[1].x / TILE_SIZE;
+        let tile_y = starts[1].y / TILE_SIZE + 1;
+        let width = state.level.width_in_tiles;
+        state.level.grid[(tile_x + tile_y * width) as usize] = true;
+        assert_eq!(state.get_respawn_point(0, &ruleset), starts[3]);
+    }
+
+    fn hitstop_ruleset() -> Ruleset {
+        return Ruleset {
+            hitstop: 6,
+            ..Ruleset::default()
+        };
+    }
+
+    fn no_inputs() -> Vec<(Input, InputStatus)> {
+        return vec![
+            (
+                Input {
+                    inp: 0,
+                    aim: NO_AIM
+                },
+                InputStatus::Confirmed
+            );
+            2
+        ];
+    }
+
+    #[test]
+    fn classic_kills_dont_freeze_the_game() {
+        let state = resolve(
+            Attack::Boomerang,
+            Attack::Nothing,
+            &Ruleset::default(),
+        );
+        assert_eq!(state.hitstop_frames, 0);
+    }
+
+    #[test]
+    fn hitstop_holds_back_the_round_clocks() {
+        let ruleset = hitstop_ruleset();
+        let hurtboxes = Hurtboxes::load().unwrap();
+        let mut state =
+            resolve(Attack::Boomerang, Attack::Nothing, &ruleset);
+        assert_eq!(state.hitstop_frames, 6);
+        let round_start_frame = state.round_start_frame;
+        let round_end_frame = state.round_end_frame;
+        let position = state.players[0].hitbox.y;
+        // the killing boomerang was never really thrown
+        state.boomerangs[0] = Boomerang::new();
+
+        for _ in 0..6 {
+            state.advance(no_inputs(), &ruleset, &hurtboxes);
+        }
+        assert_eq!(state.hitstop_frames, 0);
+        assert_eq!(state.round_start_frame, round_start_frame + 6);
+        assert_eq!(state.round_end_frame, round_end_frame + 6);
+        assert_eq!(state.players[0].hitbox.y, position);
+
+        state.advance(no_inputs(), &ruleset, &hurtboxes);
+        assert_eq!(state.round_start_frame, round_start_frame + 6);
+        assert_eq!(state.round_end_frame, round_end_frame + 6);
     }
 }
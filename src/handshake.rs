@@ -0,0 +1,452 @@
+use instant::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+// tags our packets so stray GGRS traffic is never mistaken for them
+const MAGIC: u32 = 0xE5B0_0026;
+const RECV_BUFFER_SIZE: usize = 1024;
+
+const PING_INTERVAL: Duration = Duration::from_millis(100);
+const MEASURE_DURATION: Duration = Duration::from_secs(3);
+const MIN_PING_SAMPLES: usize = 10;
+// keep answering for a while after we are done so the peer can finish
+const LINGER_DURATION: Duration = Duration::from_millis(500);
+
+pub const MAX_INPUT_DELAY: usize = 8;
+// how many frames of rollback we accept before adding input delay
+pub const TARGET_ROLLBACK_FRAMES: usize = 2;
+
+// anything that would desync the simulation if the peers disagreed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchInfo {
+    pub version: String,
+    pub level_hash: u64,
+    pub ruleset_hash: u64,
+    pub constants_hash: u64,
+    pub hurtboxes_hash: u64,
+    pub num_players: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    // the skin is only a preference, so it can differ between peers
+    Hello { match_info: MatchInfo, skin: String },
+    Ping { sent_micros: u64 },
+    Pong { sent_micros: u64 },
+    Ready { input_delay: usize, received: bool },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Packet {
+    magic: u32,
+    message: Message,
+}
+
+struct Peer {
+    // unknown until a joining peer first contacts us
+    addr: Option<SocketAddr>,
+    round_trip_times: Vec<u64>,
+    match_info: Option<MatchInfo>,
+    skin: Option<String>,
+    input_delay: Option<usize>,
+    has_our_delay: bool,
+}
+
+pub enum HandshakeStatus {
+    Measuring,
+    Exchanging,
+    Done(HandshakeResult),
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct HandshakeResult {
+    pub local_port: u16,
+    pub remote_addrs: Vec<SocketAddr>,
+    pub input_delay: usize,
+    pub remote_input_delays: Vec<usize>,
+    // the skin each peer picked, in the same order
+    pub remote_skins: Vec<String>,
+    pub ping_ms: u64,
+}
+
+pub struct Handshake {
+    socket: UdpSocket,
+    local_port: u16,
+    peers: Vec<Peer>,
+    match_info: MatchInfo,
+    skin: String,
+    input_delay_override: Option<usize>,
+    input_delay: Option<usize>,
+    started: Instant,
+    last_send: Instant,
+    finished: Option<Instant>,
+    buffer: [u8; RECV_BUFFER_SIZE],
+}
+
+impl Handshake {
+    pub fn new(
+        local_port: u16,
+        remote_addrs: Vec<Option<SocketAddr>>,
+        match_info: MatchInfo,
+        skin: String,
+        input_delay_override: Option<usize>,
+    ) -> Result<Self, std::io::Error> {
+        let addr =
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), local_port);
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        let peers = remote_addrs
+            .into_iter()
+            .map(|addr| Peer {
+                addr,
+                round_trip_times: Vec::new(),
+                match_info: None,
+                skin: None,
+                input_delay: None,
+                has_our_delay: false,
+            })
+            .collect();
+        let now = Instant::now();
+        return Ok(Self {
+            socket,
+            local_port,
+            peers,
+            match_info,
+            skin,
+            input_delay_override: input_delay_override
+                .map(|delay| delay.min(MAX_INPUT_DELAY)),
+            input_delay: None,
+            started: now,
+            last_send: now - PING_INTERVAL,
+            finished: None,
+            buffer: [0; RECV_BUFFER_SIZE],
+        });
+    }
+
+    // call this regularly; it never blocks
+    pub fn poll(&mut self) -> HandshakeStatus {
+        self.receive_all_messages();
+
+        for peer in &self.peers {
+            if let Some(match_info) = &peer.match_info {
+                if let Some(reason) =
+                    get_mismatch_reason(&self.match_info, match_info)
+                {
+                    let addr = peer.addr.unwrap();
+                    // make sure the peer sees the mismatch too
+                    self.send_to(
+                        Message::Hello {
+                            match_info: self.match_info.clone(),
+                            skin: self.skin.clone(),
+                        },
+                        &addr,
+                    );
+                    return HandshakeStatus::Failed(format!(
+                        "cannot start match with {}: {}",
+                        addr, reason
+                    ));
+                }
+            }
+        }
+
+        if self.input_delay.is_none() && self.has_measured() {
+            let delay = match self.input_delay_override {
+                Some(delay) => delay,
+                None => input_delay_for_ping(self.ping_ms()),
+            };
+            println!(
+                "measured ping {}ms, using input delay {}",
+                self.ping_ms(),
+                delay
+            );
+            self.input_delay = Some(delay);
+        }
+
+        if Instant::now().duration_since(self.last_send) >= PING_INTERVAL {
+            self.last_send = Instant::now();
+            let sent_micros = self.elapsed_micros();
+            for peer_num in 0..self.peers.len() {
+                let addr = match self.peers[peer_num].addr {
+                    Some(addr) => addr,
+                    None => continue,
+                };
+                self.send_to(
+                    Message::Hello {
+                        match_info: self.match_info.clone(),
+                        skin: self.skin.clone(),
+                    },
+                    &addr,
+                );
+                self.send_to(Message::Ping { sent_micros }, &addr);
+                if let Some(input_delay) = self.input_delay {
+                    let received =
+                        self.peers[peer_num].input_delay.is_some();
+                    self.send_to(
+                        Message::Ready {
+                            input_delay,
+                            received,
+                        },
+                        &addr,
+                    );
+                }
+            }
+        }
+
+        if self.input_delay.is_none() {
+            return HandshakeStatus::Measuring;
+        }
+        let all_exchanged = self.peers.iter().all(|peer| {
+            peer.match_info.is_some()
+                && peer.input_delay.is_some()
+                && peer.has_our_delay
+        });
+        if !all_exchanged {
+            return HandshakeStatus::Exchanging;
+        }
+        let finished = *self.finished.get_or_insert(Instant::now());
+        if Instant::now().duration_since(finished) < LINGER_DURATION {
+            return HandshakeStatus::Exchanging;
+        }
+        return HandshakeStatus::Done(HandshakeResult {
+            local_port: self.local_port,
+            remote_addrs: self
+                .peers
+                .iter()
+                .map(|peer| peer.addr.unwrap())
+                .collect(),
+            input_delay: self.input_delay.unwrap(),
+            remote_input_delays: self
+                .peers
+                .iter()
+                .map(|peer| peer.input_delay.unwrap())
+                .collect(),
+            remote_skins: self
+                .peers
+                .iter()
+                .map(|peer| peer.skin.clone().unwrap())
+                .collect(),
+            ping_ms: self.ping_ms(),
+        });
+    }
+
+    pub fn is_waiting_for_peers(&self) -> bool {
+        return self
+            .peers
+            .iter()
+            .any(|peer| peer.addr.is_none() || peer.match_info.is_none());
+    }
+
+    fn has_measured(&self) -> bool {
+        if self.input_delay_override.is_some() {
+            return true;
+        }
+        return Instant::now().duration_since(self.started)
+            >= MEASURE_DURATION
+            && self.peers.iter().all(|peer| {
+                peer.round_trip_times.len() >= MIN_PING_SAMPLES
+            });
+    }
+
+    // the worst peer decides, using the median to ignore spikes
+    pub fn ping_ms(&self) -> u64 {
+        let mut ping_ms = 0;
+        for peer in &self.peers {
+            if peer.round_trip_times.is_empty() {
+                continue;
+            }
+            let mut sorted = peer.round_trip_times.clone();
+            sorted.sort();
+            let median_micros = sorted[sorted.len() / 2];
+            ping_ms = ping_ms.max(median_micros / 1000);
+        }
+        return ping_ms;
+    }
+
+    fn elapsed_micros(&self) -> u64 {
+        return Instant::now().duration_since(self.started).as_micros()
+            as u64;
+    }
+
+    fn send_to(&self, message: Message, addr: &SocketAddr) {
+        let packet = Packet {
+            magic: MAGIC,
+            message,
+        };
+        let buf = bincode::serialize(&packet).unwrap();
+        // the peer may not be listening yet, so errors are expected
+        let _ = self.socket.send_to(&buf, addr);
+    }
+
+    fn receive_all_messages(&mut self) {
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((number_of_bytes, src_addr)) => {
+                    let packet: Packet = match bincode::deserialize(
+                        &self.buffer[0..number_of_bytes],
+                    ) {
+                        Ok(packet) => packet,
+                        Err(_) => continue,
+                    };
+                    if packet.magic != MAGIC {
+                        continue;
+                    }
+                    self.handle_message(packet.message, src_addr);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    return;
+                }
+                Err(ref err)
+                    if err.kind() == ErrorKind::ConnectionReset =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    println!("handshake socket error: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, src_addr: SocketAddr) {
+        let known_peer_num = self
+            .peers
+            .iter()
+            .position(|peer| peer.addr == Some(src_addr));
+        let peer_num = match known_peer_num {
+            Some(peer_num) => peer_num,
+            None => {
+                // a host accepts whoever contacts it first
+                match self
+                    .peers
+                    .iter()
+                    .position(|peer| peer.addr.is_none())
+                {
+                    Some(peer_num) => {
+                        println!("peer joined from {}", src_addr);
+                        self.peers[peer_num].addr = Some(src_addr);
+                        peer_num
+                    }
+                    None => return,
+                }
+            }
+        };
+        match message {
+            Message::Hello { match_info, skin } => {
+                self.peers[peer_num].match_info = Some(match_info);
+                self.peers[peer_num].skin = Some(skin);
+            }
+            Message::Ping { sent_micros } => {
+                self.send_to(Message::Pong { sent_micros }, &src_addr);
+            }
+            Message::Pong { sent_micros } => {
+                let now = self.elapsed_micros();
+                if now >= sent_micros {
+                    self.peers[peer_num]
+                        .round_trip_times
+                        .push(now - sent_micros);
+                }
+            }
+            Message::Ready {
+                input_delay,
+                received,
+            } => {
+                self.peers[peer_num].input_delay = Some(input_delay);
+                if received {
+                    self.peers[peer_num].has_our_delay = true;
+                }
+            }
+        }
+    }
+}
+
+fn get_mismatch_reason(
+    local: &MatchInfo,
+    remote: &MatchInfo,
+) -> Option<String> {
+    if local.version != remote.version {
+        return Some(format!(
+            "game version differs (ours {}, theirs {})",
+            local.version, remote.version
+        ));
+    }
+    if local.num_players != remote.num_players {
+        return Some(format!(
+            "player count differs (ours {}, theirs {})",
+            local.num_players, remote.num_players
+        ));
+    }
+    if local.level_hash != remote.level_hash {
+        return Some("level.oel differs between the peers".to_string());
+    }
+    if local.ruleset_hash != remote.ruleset_hash {
+        return Some("the rulesets differ between the peers".to_string());
+    }
+    if local.constants_hash != remote.constants_hash {
+        return Some(
+            "physics constants differ between the peers".to_string(),
+        );
+    }
+    if local.hurtboxes_hash != remote.hurtboxes_hash {
+        return Some(
+            "hurtboxes.xml differs between the peers".to_string(),
+        );
+    }
+    return None;
+}
+
+// remote inputs arrive half a round trip late; every frame of that
+// which input delay doesn't hide has to be rolled back
+pub fn input_delay_for_ping(ping_ms: u64) -> usize {
+    let one_way_micros = ping_ms * 1000 / 2;
+    // rounded up so a partially late frame still counts
+    let late_frames =
+        (one_way_micros * crate::FPS as u64).div_ceil(1_000_000) as usize;
+    return late_frames
+        .saturating_sub(TARGET_ROLLBACK_FRAMES)
+        .min(MAX_INPUT_DELAY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_delay_hides_what_rollback_cant() {
+        // a frame is 16.7ms, so 33ms one way is two frames late and
+        // fully covered by rollback
+        assert_eq!(input_delay_for_ping(0), 0);
+        assert_eq!(input_delay_for_ping(66), 0);
+        assert_eq!(input_delay_for_ping(67), 1);
+        // exactly three frames late needs one frame of delay, not two
+        assert_eq!(input_delay_for_ping(100), 1);
+        assert_eq!(input_delay_for_ping(101), 2);
+        assert_eq!(input_delay_for_ping(133), 2);
+        assert_eq!(input_delay_for_ping(134), 3);
+    }
+
+    #[test]
+    fn peers_must_agree_on_the_physics_constants() {
+        let local = MatchInfo {
+            version: "0.1.0".to_string(),
+            level_hash: 1,
+            ruleset_hash: 2,
+            constants_hash: 3,
+            hurtboxes_hash: 4,
+            num_players: 2,
+        };
+        let remote = MatchInfo {
+            constants_hash: 5,
+            ..local.clone()
+        };
+        assert_eq!(get_mismatch_reason(&local, &local.clone()), None);
+        assert!(get_mismatch_reason(&local, &remote).is_some());
+    }
+
+    #[test]
+    fn input_delay_is_capped() {
+        assert_eq!(input_delay_for_ping(10_000), MAX_INPUT_DELAY);
+    }
+}
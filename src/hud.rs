@@ -0,0 +1,435 @@
+use tetra::graphics::{
+    Color, DrawParams, ImageData, Rectangle, Texture, TextureFormat,
+};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::game::RoundResult;
+use crate::{Match, Resources};
+
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+// each row of a glyph is three bits, with the leftmost pixel highest
+const GLYPHS: [(char, [u8; 5]); 47] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b011, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
+
+// frames "READY" stays up at the start of a round
+const READY_FRAMES: i32 = 90;
+const DIM_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+// ping in milliseconds below which a connection gets 3 or 2 bars
+const GOOD_PING: u128 = 80;
+const OK_PING: u128 = 150;
+
+// a font drawn a pixel per pixel of the 320x180 canvas, scaled up by
+// whole numbers so it stays crisp. the glyphs are built into a texture
+// when the game starts
+pub struct PixelFont {
+    texture: Texture,
+}
+
+impl PixelFont {
+    pub fn new(ctx: &mut Context) -> PixelFont {
+        let width = GLYPH_WIDTH * GLYPHS.len() as i32;
+        let mut data = vec![0; (width * GLYPH_HEIGHT * 4) as usize];
+        for (glyph_num, (_, rows)) in GLYPHS.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if row >> (GLYPH_WIDTH - 1 - x) & 1 == 0 {
+                        continue;
+                    }
+                    let pixel_x = glyph_num as i32 * GLYPH_WIDTH + x;
+                    let index =
+                        ((y as i32 * width + pixel_x) * 4) as usize;
+                    data[index..index + 4].copy_from_slice(&[255; 4]);
+                }
+            }
+        }
+        let image = ImageData::from_data(
+            width,
+            GLYPH_HEIGHT,
+            TextureFormat::Rgba8,
+            data,
+        )
+        .unwrap();
+        return PixelFont {
+            texture: image.to_texture(ctx).unwrap(),
+        };
+    }
+
+    pub fn width(&self, text: &str, scale: i32) -> f32 {
+        let length = text.chars().count() as i32;
+        if length == 0 {
+            return 0.0;
+        }
+        return ((GLYPH_WIDTH + 1) * length - 1) as f32 * scale as f32;
+    }
+
+    pub fn height(&self, scale: i32) -> f32 {
+        return (GLYPH_HEIGHT * scale) as f32;
+    }
+
+    // lowercase letters are drawn as uppercase, and anything else
+    // without a glyph is left as a gap
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        text: &str,
+        position: Vec2<f32>,
+        scale: i32,
+        color: Color,
+    ) {
+        for (char_num, c) in text.chars().enumerate() {
+            let c = c.to_ascii_uppercase();
+            let glyph_num =
+                match GLYPHS.iter().position(|glyph| glyph.0 == c) {
+                    Some(glyph_num) => glyph_num,
+                    None => continue,
+                };
+            self.texture.draw_region(
+                ctx,
+                Rectangle::new(
+                    (glyph_num as i32 * GLYPH_WIDTH) as f32,
+                    0.0,
+                    GLYPH_WIDTH as f32,
+                    GLYPH_HEIGHT as f32,
+                ),
+                DrawParams::new()
+                    .position(Vec2::new(
+                        position.x.round()
+                            + (char_num as i32 * (GLYPH_WIDTH + 1) * scale)
+                                as f32,
+                        position.y.round(),
+                    ))
+                    .scale(Vec2::new(scale as f32, scale as f32))
+                    .color(color),
+            );
+        }
+    }
+
+    pub fn draw_centered(
+        &self,
+        ctx: &mut Context,
+        text: &str,
+        center_x: f32,
+        y: f32,
+        scale: i32,
+        color: Color,
+    ) {
+        let left = center_x - self.width(text, scale) / 2.0;
+        self.draw(ctx, text, Vec2::new(left, y), scale, color);
+    }
+}
+
+// scores, the round and its timer along the top, stocks along the
+// bottom, and the round's start and end in the middle
+pub fn draw(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    draw_scores(ctx, resources, current_match);
+    draw_round_info(ctx, resources, current_match);
+    draw_stocks(ctx, resources, current_match);
+    draw_announcement(ctx, resources, current_match);
+}
+
+// each team's players and score, with the first half of the teams in
+// the top left corner and the rest in the top right, leaving the middle
+// for the timer
+fn draw_scores(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    let state = &current_match.game.state;
+    let ruleset = &current_match.game.ruleset;
+    let mut teams: Vec<usize> = Vec::new();
+    for player_num in 0..state.players.len() {
+        let team = ruleset.team(player_num);
+        if !teams.contains(&team) {
+            teams.push(team);
+        }
+    }
+    let num_left = teams.len().div_ceil(2);
+    let mut left_x = 4.0;
+    let mut right_x = 316.0;
+    for (entry_num, team) in teams.iter().enumerate() {
+        let players: Vec<usize> = (0..state.players.len())
+            .filter(|player_num| ruleset.team(*player_num) == *team)
+            .collect();
+        let score = state.scores[*team].to_string();
+        let icons_width = players.len() as f32 * 9.0;
+        let width = icons_width + 2.0 + resources.font.width(&score, 2);
+        let x = if entry_num < num_left {
+            left_x += width + 8.0;
+            left_x - width - 8.0
+        } else {
+            right_x -= width + 8.0;
+            right_x + 8.0
+        };
+        for (icon_num, player_num) in players.iter().enumerate() {
+            let position = Vec2::new(x + icon_num as f32 * 9.0, 3.0);
+            draw_player_icon(
+                ctx,
+                resources,
+                current_match,
+                *player_num,
+                position,
+            );
+            draw_connection(
+                ctx,
+                resources,
+                current_match,
+                *player_num,
+                position + Vec2::new(1.0, 13.0),
+            );
+        }
+        resources.font.draw(
+            ctx,
+            &score,
+            Vec2::new(x + icons_width + 2.0, 4.0),
+            2,
+            Color::WHITE,
+        );
+    }
+}
+
+fn draw_player_icon(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+    player_num: usize,
+    position: Vec2<f32>,
+) {
+    let name = format!("player_{}", current_match.skins[player_num]);
+    let sprite = &resources.sprites[&name];
+    let idle = &sprite.animations["idle"].frames[0];
+    resources.textures[&name].draw_region(
+        ctx,
+        Rectangle::new(
+            idle.x as f32,
+            idle.y as f32,
+            sprite.frame_width as f32,
+            sprite.frame_height as f32,
+        ),
+        DrawParams::new().position(position),
+    );
+}
+
+// three bars under a remote player, fewer and redder the worse their
+// ping. nothing is drawn for players on this machine
+fn draw_connection(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+    player_num: usize,
+    position: Vec2<f32>,
+) {
+    if !current_match
+        .sess
+        .remote_player_handles()
+        .contains(&player_num)
+    {
+        return;
+    }
+    let (bars, color) = match current_match.sess.network_stats(player_num)
+    {
+        Ok(stats) if stats.ping < GOOD_PING => {
+            (3, Color::rgb(0.3, 0.9, 0.3))
+        }
+        Ok(stats) if stats.ping < OK_PING => {
+            (2, Color::rgb(1.0, 0.9, 0.35))
+        }
+        Ok(_) => (1, Color::rgb(0.9, 0.25, 0.2)),
+        // no stats until the connection has settled
+        Err(_) => (0, Color::WHITE),
+    };
+    for bar in 0..3 {
+        let height = 2.0 + bar as f32;
+        resources.rectangle.draw(
+            ctx,
+            DrawParams::new()
+                .position(
+                    position + Vec2::new(bar as f32 * 2.0, 4.0 - height),
+                )
+                .scale(Vec2::new(1.0, height))
+                .color(if bar < bars { color } else { DIM_COLOR }),
+        );
+    }
+}
+
+// the timer, or sudden death once it runs out, with the round number
+// and connection info under it
+fn draw_round_info(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    let state = &current_match.game.state;
+    let ruleset = &current_match.game.ruleset;
+    let mut y = 4.0;
+    let timer_text = if state.is_sudden_death {
+        Some("SUDDEN DEATH".to_string())
+    } else if ruleset.round_time > 0 && !state.is_training {
+        // round up so the timer reads 0:00 as sudden death starts
+        let seconds = (state.get_round_time_left(ruleset) + 59) / 60;
+        Some(format!("{}:{:02}", seconds / 60, seconds % 60))
+    } else {
+        None
+    };
+    if let Some(timer_text) = timer_text {
+        resources.font.draw_centered(
+            ctx,
+            &timer_text,
+            160.0,
+            y,
+            2,
+            Color::WHITE,
+        );
+        y += resources.font.height(2) + 3.0;
+    }
+    resources.font.draw_centered(
+        ctx,
+        &format!("ROUND {}", state.round),
+        160.0,
+        y,
+        1,
+        Color::WHITE,
+    );
+    y += resources.font.height(1) + 3.0;
+    let info_text = match &current_match.training {
+        Some(training) => training.get_status_text(),
+        None => current_match.info_text.clone(),
+    };
+    resources.font.draw_centered(
+        ctx,
+        &info_text,
+        160.0,
+        y,
+        1,
+        Color::rgba(1.0, 1.0, 1.0, 0.6),
+    );
+}
+
+// a row of idle frames along the bottom of the screen for each
+// player's remaining stocks, with lost ones dimmed
+fn draw_stocks(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    let stocks = current_match.game.ruleset.stocks;
+    if stocks <= 1 {
+        return;
+    }
+    for (player_num, player) in
+        current_match.game.state.players.iter().enumerate()
+    {
+        let name = format!("player_{}", current_match.skins[player_num]);
+        let sprite = &resources.sprites[&name];
+        let idle = &sprite.animations["idle"].frames[0];
+        for stock in 0..stocks {
+            let color = if stock < stocks - player.stocks_lost {
+                Color::WHITE
+            } else {
+                DIM_COLOR
+            };
+            resources.textures[&name].draw_region(
+                ctx,
+                Rectangle::new(
+                    idle.x as f32,
+                    idle.y as f32,
+                    sprite.frame_width as f32,
+                    sprite.frame_height as f32,
+                ),
+                DrawParams::new()
+                    .position(Vec2::new(
+                        4.0 + player_num as f32 * 80.0
+                            + stock as f32
+                                * (sprite.frame_width + 1) as f32,
+                        176.0 - sprite.frame_height as f32,
+                    ))
+                    .color(color),
+            );
+        }
+    }
+}
+
+// "READY" as a round starts, and who won once it's over
+fn draw_announcement(
+    ctx: &mut Context,
+    resources: &Resources,
+    current_match: &Match,
+) {
+    let state = &current_match.game.state;
+    let ruleset = &current_match.game.ruleset;
+    let text = match state.round_result {
+        Some(RoundResult::Draw) | Some(RoundResult::Replay) => {
+            "DRAW".to_string()
+        }
+        Some(RoundResult::Win { team }) if ruleset.teams => {
+            format!("TEAM {} WINS", team + 1)
+        }
+        Some(RoundResult::Win { team }) => format!("P{} WINS", team + 1),
+        None if state.frame - state.round_start_frame < READY_FRAMES => {
+            "READY".to_string()
+        }
+        None => return,
+    };
+    let scale = 5;
+    resources.font.draw_centered(
+        ctx,
+        &text,
+        160.0,
+        90.0 - resources.font.height(scale) / 2.0,
+        scale,
+        Color::WHITE,
+    );
+}
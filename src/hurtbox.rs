@@ -0,0 +1,97 @@
+use quick_xml::de::from_str;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::utils::{fnv1a_hash, Hitbox};
+
+const HURTBOXES_PATH: &str = "./resources/hurtboxes.xml";
+
+// where a player can be hit during each animation, relative to the top
+// left of their terrain hitbox while facing right. animations without
+// an entry are hurt across the whole terrain hitbox
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "hurtboxes")]
+pub struct Hurtboxes {
+    #[serde(rename = "hurtbox", default)]
+    entries: Vec<HurtboxData>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct HurtboxData {
+    animation: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Hurtboxes {
+    pub fn load() -> Result<Self, String> {
+        let xml = fs::read_to_string(HURTBOXES_PATH).map_err(|err| {
+            format!("could not read {}: {}", HURTBOXES_PATH, err)
+        })?;
+        return from_str(&xml).map_err(|err| {
+            format!("could not parse {}: {}", HURTBOXES_PATH, err)
+        });
+    }
+
+    pub fn get(&self, animation: &str) -> Option<Hitbox> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.animation == animation)?;
+        return Some(Hitbox {
+            x: entry.x,
+            y: entry.y,
+            width: entry.width,
+            height: entry.height,
+        });
+    }
+
+    pub fn hash(&self) -> u64 {
+        return fnv1a_hash(&bincode::serialize(self).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{self, Player};
+
+    #[test]
+    fn animations_without_an_entry_use_the_terrain_hitbox() {
+        let hurtboxes = Hurtboxes::load().unwrap();
+        assert!(hurtboxes.get("slide").is_some());
+        assert!(hurtboxes.get("idle").is_none());
+    }
+
+    #[test]
+    fn hurtboxes_are_mirrored_when_facing_left() {
+        let hurtboxes = Hurtboxes::load().unwrap();
+        let offset = Hitbox {
+            x: 1000,
+            y: 2000,
+            width: 3000,
+            height: 4000,
+        };
+        assert!(offset.x + offset.width < player::WIDTH);
+
+        let mut right = Player::new(0, 0, false);
+        right.hurtbox_offset = offset.clone();
+        let mut left = Player::new(0, 0, true);
+        left.hurtbox_offset = offset;
+
+        assert_eq!(right.hurtbox().x, 1000);
+        assert_eq!(left.hurtbox().x, player::WIDTH - 1000 - 3000);
+        assert_eq!(left.hurtbox().y, right.hurtbox().y);
+        assert_eq!(left.hurtbox().width, 3000);
+
+        // the slide box sticks out equally on both sides once mirrored
+        let slide = hurtboxes.get("slide").unwrap();
+        left.hurtbox_offset = slide.clone();
+        right.hurtbox_offset = slide.clone();
+        let left_edge = left.hurtbox().x;
+        let right_edge = right.hurtbox().x + slide.width;
+        assert_eq!(-left_edge, right_edge - player::WIDTH);
+    }
+}
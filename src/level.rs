@@ -1,15 +1,19 @@
-use crate::utils::IntVector2D;
+use crate::utils::{do_hitboxes_overlap, fnv1a_hash, Hitbox, IntVector2D};
 use quick_xml::de::from_str;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 
 pub const TILE_SIZE: i32 = 4000;
 
+// the grid is part of the rollback state, since sudden death can fill
+// it in
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Level {
     pub width_in_tiles: i32,
     pub height_in_tiles: i32,
     pub grid: Vec<bool>,
-    pub player_starts: (IntVector2D, IntVector2D),
+    // one per player1..player4 entity, in order
+    pub player_starts: Vec<IntVector2D>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -20,21 +24,17 @@ struct LevelData {
     entities: EntityData,
 }
 
+// player3 and player4 are only needed for matches with more players
 #[derive(Debug, Deserialize, PartialEq)]
 struct EntityData {
-    player1: Player1Data,
-    player2: Player2Data,
+    player1: PlayerStartData,
+    player2: PlayerStartData,
+    player3: Option<PlayerStartData>,
+    player4: Option<PlayerStartData>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
-struct Player1Data {
-    id: i32,
-    x: i32,
-    y: i32,
-}
-
-#[derive(Debug, Deserialize, PartialEq)]
-struct Player2Data {
+struct PlayerStartData {
     id: i32,
     x: i32,
     y: i32,
@@ -54,16 +54,19 @@ impl Level {
             }
             grid.push(c == '1');
         }
-        let player_starts = (
-            IntVector2D {
-                x: data.entities.player1.x * 1000,
-                y: data.entities.player1.y * 1000,
-            },
-            IntVector2D {
-                x: data.entities.player2.x * 1000,
-                y: data.entities.player2.y * 1000,
-            },
-        );
+        let player_starts = [
+            Some(data.entities.player1),
+            Some(data.entities.player2),
+            data.entities.player3,
+            data.entities.player4,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|start| IntVector2D {
+            x: start.x * 1000,
+            y: start.y * 1000,
+        })
+        .collect();
         Self {
             width_in_tiles,
             height_in_tiles,
@@ -83,4 +86,106 @@ impl Level {
         return self.grid
             [(tile_x + tile_y * self.width_in_tiles) as usize];
     }
+
+    // fills in the ring of tiles this many tiles in from the edges.
+    // returns false once there's no ring left to close
+    pub fn close_ring(&mut self, ring: i32) -> bool {
+        let right = self.width_in_tiles - 1 - ring;
+        let bottom = self.height_in_tiles - 1 - ring;
+        if ring > right || ring > bottom {
+            return false;
+        }
+        for tile_y in ring..=bottom {
+            for tile_x in ring..=right {
+                if tile_x == ring
+                    || tile_x == right
+                    || tile_y == ring
+                    || tile_y == bottom
+                {
+                    self.grid[(tile_x + tile_y * self.width_in_tiles)
+                        as usize] = true;
+                }
+            }
+        }
+        return true;
+    }
+
+    // whether the hitbox overlaps any solid tile
+    pub fn collide(&self, hitbox: &Hitbox) -> bool {
+        return self.checked_tiles(hitbox).any(|(tile_x, tile_y)| {
+            if !self.check_grid(tile_x, tile_y) {
+                return false;
+            }
+            let grid_hitbox = Hitbox {
+                x: tile_x * TILE_SIZE,
+                y: tile_y * TILE_SIZE,
+                width: TILE_SIZE,
+                height: TILE_SIZE,
+            };
+            return do_hitboxes_overlap(hitbox, &grid_hitbox);
+        });
+    }
+
+    // the grid cells collide looks at for this hitbox
+    pub fn checked_tiles(
+        &self,
+        hitbox: &Hitbox,
+    ) -> impl Iterator<Item = (i32, i32)> {
+        let tile_x = hitbox.x / TILE_SIZE;
+        let tile_y = hitbox.y / TILE_SIZE;
+        // We use (dividend + divisor - 1) / divisor here
+        // to get integer division that rounds up
+        let tile_width = (hitbox.width + TILE_SIZE - 1) / TILE_SIZE;
+        let tile_height = (hitbox.height + TILE_SIZE - 1) / TILE_SIZE;
+        return (0..(tile_width + 1)).flat_map(move |check_x| {
+            (0..(tile_height + 1))
+                .map(move |check_y| (tile_x + check_x, tile_y + check_y))
+        });
+    }
+
+    pub fn hash(&self) -> u64 {
+        let data = bincode::serialize(&(
+            self.width_in_tiles,
+            self.height_in_tiles,
+            &self.grid,
+            &self.player_starts,
+        ))
+        .unwrap();
+        return fnv1a_hash(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_solid(level: &Level, tile_x: i32, tile_y: i32) -> bool {
+        return level.grid
+            [(tile_x + tile_y * level.width_in_tiles) as usize];
+    }
+
+    #[test]
+    fn rings_close_from_the_edges_in() {
+        let mut level = Level {
+            width_in_tiles: 4,
+            height_in_tiles: 3,
+            grid: vec![false; 12],
+            player_starts: Vec::new(),
+        };
+
+        assert!(level.close_ring(0));
+        for tile_x in 0..4 {
+            assert!(is_solid(&level, tile_x, 0));
+            assert!(is_solid(&level, tile_x, 2));
+        }
+        assert!(is_solid(&level, 0, 1));
+        assert!(is_solid(&level, 3, 1));
+        assert!(!is_solid(&level, 1, 1));
+        assert!(!is_solid(&level, 2, 1));
+
+        assert!(level.close_ring(1));
+        assert!(level.grid.iter().all(|is_solid| *is_solid));
+
+        assert!(!level.close_ring(2));
+    }
 }
@@ -1,161 +1,490 @@
+// the code returns explicitly, steps the simulation with long argument
+// lists and indexes players by number, so these lints don't apply
+#![allow(
+    clippy::needless_return,
+    clippy::too_many_arguments,
+    clippy::needless_range_loop
+)]
+
 use ggrs::{
-    GGRSError, P2PSession, PlayerType, SessionBuilder, SessionState,
-    UdpNonBlockingSocket,
+    GGRSError, GGRSEvent, P2PSession, PlayerType, SessionBuilder,
+    SessionState, UdpNonBlockingSocket,
 };
 use instant::{Duration, Instant};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use structopt::StructOpt;
-use tetra::audio::{Sound, SoundInstance};
-use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::audio::{self, Sound, SoundInstance};
+use tetra::graphics::mesh::{GeometryBuilder, Mesh, ShapeStyle};
 use tetra::graphics::scaling::{ScalingMode, ScreenScaler};
 use tetra::graphics::text::{Font, Text};
-use tetra::graphics::{self, Color, DrawParams, Rectangle, Texture};
-use tetra::math::Vec2;
+use tetra::graphics::{
+    self, Color, DrawParams, ImageData, Rectangle, Texture,
+};
+use tetra::input::{self, Key, KeyModifier};
+use tetra::math::{Mat4, Vec2};
 use tetra::time::Timestep;
-use tetra::{Context, ContextBuilder, Event, State};
+use tetra::{window, Context, ContextBuilder, Event, State};
 
 mod boomerang;
 mod curtain;
+mod debug;
+mod effects;
 mod game;
+mod handshake;
+mod hud;
+mod hurtbox;
 mod level;
+mod menu;
+mod palette;
 mod particle;
 mod player;
+mod presentation;
+mod ruleset;
+mod settings;
+mod training;
 mod utils;
 
 use boomerang::Boomerang;
-use game::{GGRSConfig, Game};
+use curtain::Transition;
+use effects::{Afterimage, Effects, AFTERIMAGE_FRAMES, FLASH_FRAMES};
+use game::{GGRSConfig, Game, MAX_PLAYERS, MIN_PLAYERS};
+use handshake::{Handshake, HandshakeResult, HandshakeStatus};
+use hud::PixelFont;
 use level::{Level, TILE_SIZE};
+use menu::{Menu, MenuAction};
+use palette::Palettes;
 use particle::{
     Particle, GROUND_DUST_ANIMATION_FRAMES, GROUND_DUST_ANIMATION_SPEED,
     SIMPLE_ANIMATION_FRAMES, SIMPLE_ANIMATION_SPEED,
 };
-use curtain::MAX_OPACITY;
 use player::Player;
+use presentation::Presentation;
+use ruleset::Ruleset;
+use settings::Settings;
+use training::Training;
+use utils::IntVector2D;
 
 const FPS: f64 = 60.0;
 
+// farther than any corner of the screen is from any point on it
+const IRIS_MAX_RADIUS: f32 = 400.0;
+const IRIS_SEGMENTS: i32 = 48;
+
 #[derive(StructOpt)]
 struct Opt {
     #[structopt(short, long)]
-    local_port: u16,
+    local_port: Option<u16>,
+    // skips the menu and connects to these players right away
     #[structopt(short, long)]
     players: Vec<String>,
+    // skips the ping measurement and uses this delay instead
+    #[structopt(long)]
+    input_delay: Option<usize>,
+    // plays with resources/rulesets/<name>.xml instead of the saved one
+    #[structopt(long)]
+    ruleset: Option<String>,
 }
 
 fn main() -> tetra::Result {
     println!("starting game");
     // read cmd line arguments
     let opt = Opt::from_args();
+    let settings = Settings::load();
+    let (window_width, window_height) = settings.window_size();
+
+    ContextBuilder::new(
+        "esport heaven online",
+        window_width,
+        window_height,
+    )
+    .quit_on_escape(false)
+    .vsync(settings.vsync)
+    .resizable(true)
+    .fullscreen(settings.fullscreen)
+    .timestep(Timestep::Variable)
+    .build()?
+    .run(|ctx| {
+        let resources = Resources::new(ctx);
+        let scaler = ScreenScaler::with_window_size(
+            ctx,
+            320,
+            180,
+            get_scaling_mode(&settings),
+        )?;
+        audio::set_master_volume(ctx, settings.master_volume());
+
+        let scene = if opt.players.is_empty() {
+            Scene::Menu(Menu::new())
+        } else {
+            let local_port = opt
+                .local_port
+                .expect("--local-port is required with --players");
+            assert!(
+                (MIN_PLAYERS..=MAX_PLAYERS).contains(&opt.players.len()),
+                "--players takes {} to {} players",
+                MIN_PLAYERS,
+                MAX_PLAYERS
+            );
+            let players: Vec<PlayerSlot> = opt
+                .players
+                .iter()
+                .map(|player_addr| {
+                    if player_addr == "localhost" {
+                        PlayerSlot::Local
+                    } else {
+                        PlayerSlot::Remote(Some(
+                            player_addr.parse().unwrap(),
+                        ))
+                    }
+                })
+                .collect();
+            let ruleset_name =
+                opt.ruleset.as_ref().unwrap_or(&settings.ruleset);
+            let has_remote_players = players
+                .iter()
+                .any(|player| matches!(player, PlayerSlot::Remote(_)));
+            if has_remote_players {
+                start_connecting(
+                    local_port,
+                    players,
+                    opt.input_delay,
+                    ruleset_name,
+                    &settings.skin,
+                )
+            } else {
+                start_offline(
+                    players.len(),
+                    false,
+                    ruleset_name,
+                    &settings.skin,
+                    &resources.palettes,
+                )
+            }
+        };
+
+        Ok(Esport {
+            resources,
+            scaler,
+            settings,
+            scene,
+        })
+    })
+}
+
+// who controls each player handle, before the session is started
+#[derive(Clone, Copy)]
+enum PlayerSlot {
+    Local,
+    // the address is unknown when hosting until the peer contacts us
+    Remote(Option<SocketAddr>),
+}
+
+struct Esport {
+    resources: Resources,
+    scaler: ScreenScaler,
+    settings: Settings,
+    scene: Scene,
+}
+
+impl Esport {
+    fn apply_settings(&mut self, ctx: &mut Context) -> tetra::Result {
+        if window::is_fullscreen(ctx) != self.settings.fullscreen {
+            window::set_fullscreen(ctx, self.settings.fullscreen)?;
+        }
+        if !self.settings.fullscreen {
+            let (width, height) = self.settings.window_size();
+            if window::get_size(ctx) != (width, height) {
+                window::set_size(ctx, width, height)?;
+            }
+        }
+        if window::is_vsync_enabled(ctx) != self.settings.vsync {
+            window::set_vsync(ctx, self.settings.vsync)?;
+        }
+        self.scaler.set_mode(get_scaling_mode(&self.settings));
+        audio::set_master_volume(ctx, self.settings.master_volume());
+        self.settings.save();
+        Ok(())
+    }
+}
+
+fn get_scaling_mode(settings: &Settings) -> ScalingMode {
+    if settings.integer_scaling {
+        return ScalingMode::ShowAllPixelPerfect;
+    }
+    return ScalingMode::ShowAll;
+}
 
+// only one scene exists at a time and they're swapped rarely, so
+// their sizes don't matter
+#[allow(clippy::large_enum_variant)]
+enum Scene {
+    Menu(Menu),
+    Connecting(Connecting),
+    Match(Match),
+}
+
+// exchanging handshakes with the peers, before the GGRS session exists
+struct Connecting {
+    // taken once the handshake is done and the match starts
+    game: Option<Game>,
+    // taken once it's done, to free its port for the GGRS session
+    handshake: Option<Handshake>,
+    players: Vec<PlayerSlot>,
+    skin: String,
+    status: String,
+}
+
+struct Match {
+    game: Game,
+    // cosmetic particles, kept out of the rollback state
+    effects: Effects,
+    presentation: Presentation,
+    // the skin each player ended up with, after clashes were resolved
+    skins: Vec<String>,
+    sess: P2PSession<GGRSConfig>,
+    last_update: Instant,
+    accumulator: Duration,
+    // dummies and their controls, in a training match
+    training: Option<Training>,
+    info_text: String,
+    // toggled with F3
+    show_debug: bool,
+}
+
+fn new_game(
+    ruleset_name: &str,
+    num_players: usize,
+) -> Result<Game, String> {
+    let ruleset = Ruleset::load(ruleset_name)?;
+    return Game::new(ruleset, num_players);
+}
+
+fn start_connecting(
+    local_port: u16,
+    players: Vec<PlayerSlot>,
+    input_delay: Option<usize>,
+    ruleset_name: &str,
+    skin: &str,
+) -> Scene {
+    let game = match new_game(ruleset_name, players.len()) {
+        Ok(game) => game,
+        Err(message) => {
+            let mut menu = Menu::new();
+            menu.set_message(&message);
+            return Scene::Menu(menu);
+        }
+    };
+    let remote_addrs = players
+        .iter()
+        .filter_map(|player| match player {
+            PlayerSlot::Remote(addr) => Some(*addr),
+            PlayerSlot::Local => None,
+        })
+        .collect();
+    let handshake = match Handshake::new(
+        local_port,
+        remote_addrs,
+        game.match_info(),
+        skin.to_string(),
+        input_delay,
+    ) {
+        Ok(handshake) => handshake,
+        Err(err) => {
+            let mut menu = Menu::new();
+            menu.set_message(&format!("could not bind port: {}", err));
+            return Scene::Menu(menu);
+        }
+    };
+    return Scene::Connecting(Connecting {
+        game: Some(game),
+        handshake: Some(handshake),
+        players,
+        skin: skin.to_string(),
+        status: "WAITING FOR PEERS".to_string(),
+    });
+}
+
+// every player plays on this machine, so nothing needs a handshake.
+// in training, only the first player is controlled
+fn start_offline(
+    num_players: usize,
+    training: bool,
+    ruleset_name: &str,
+    skin: &str,
+    palettes: &Palettes,
+) -> Scene {
+    let mut game = match new_game(ruleset_name, num_players) {
+        Ok(game) => game,
+        Err(message) => {
+            let mut menu = Menu::new();
+            menu.set_message(&message);
+            return Scene::Menu(menu);
+        }
+    };
+    game.state.is_training = training;
+    let result = HandshakeResult {
+        local_port: 0,
+        remote_addrs: Vec::new(),
+        input_delay: 0,
+        remote_input_delays: Vec::new(),
+        remote_skins: Vec::new(),
+        ping_ms: 0,
+    };
+    let players = vec![PlayerSlot::Local; num_players];
+    let training = if training {
+        Some(Training::new(&game))
+    } else {
+        None
+    };
+    return match start_match(
+        game, &players, &result, training, skin, palettes,
+    ) {
+        Ok(new_match) => Scene::Match(new_match),
+        Err(message) => {
+            let mut menu = Menu::new();
+            menu.set_message(&message);
+            Scene::Menu(menu)
+        }
+    };
+}
+
+fn start_match(
+    mut game: Game,
+    players: &[PlayerSlot],
+    result: &HandshakeResult,
+    training: Option<Training>,
+    skin: &str,
+    palettes: &Palettes,
+) -> Result<Match, String> {
     // create a GGRS session
     let mut sess_build = SessionBuilder::<GGRSConfig>::new()
-        .with_num_players(2)
+        .with_num_players(players.len())
         .with_fps(FPS as usize)
         // (optional) set expected update frequency
         .unwrap()
-        // (optional) set input delay for the local player
-        .with_input_delay(1);
-
-    // add players
-    for (i, player_addr) in opt.players.iter().enumerate() {
-        // local player
-        if player_addr == "localhost" {
-            sess_build =
-                sess_build.add_player(PlayerType::Local, i).unwrap();
-        } else {
-            // remote players
-            let remote_addr: SocketAddr = player_addr.parse().unwrap();
-            sess_build = sess_build
-                .add_player(PlayerType::Remote(remote_addr), i)
-                .unwrap();
-        }
+        // set input delay for the local player
+        .with_input_delay(result.input_delay);
+
+    // add players, with remote addresses in the order the handshake
+    // learned them
+    let mut remote_addrs = result.remote_addrs.iter();
+    for (i, player) in players.iter().enumerate() {
+        let player_type = match player {
+            PlayerSlot::Remote(_) => {
+                PlayerType::Remote(*remote_addrs.next().unwrap())
+            }
+            PlayerSlot::Local => PlayerType::Local,
+        };
+        sess_build = sess_build
+            .add_player(player_type, i)
+            .map_err(|err| err.to_string())?;
     }
 
     // start the GGRS session
-    let socket =
-        UdpNonBlockingSocket::bind_to_port(opt.local_port).unwrap();
-    let sess = sess_build.start_p2p_session(socket).unwrap();
-
-    // time variables for tick rate
-    let last_update = Instant::now();
-    let accumulator = Duration::ZERO;
-
-    ContextBuilder::new("esport heaven online", 640, 360)
-        .quit_on_escape(true)
-        .vsync(false)
-        .resizable(true)
-        .fullscreen(true)
-        .timestep(Timestep::Variable)
-        .build()?
-        .run(|ctx| {
-            let mut game = Game::new();
-            game.register_local_handles(sess.local_player_handles());
-
-            let resources = Resources::new(ctx);
-            let scaler = ScreenScaler::with_window_size(
-                ctx,
-                320,
-                180,
-                ScalingMode::ShowAll,
-            )?;
+    let socket = UdpNonBlockingSocket::bind_to_port(result.local_port)
+        .map_err(|err| format!("could not bind port: {}", err))?;
+    let sess = sess_build
+        .start_p2p_session(socket)
+        .map_err(|err| err.to_string())?;
+    game.register_local_handles(sess.local_player_handles());
 
-            Ok(Esport {
-                game,
-                resources,
-                sess,
-                last_update,
-                accumulator,
-                scaler,
-            })
+    // local players pick the same skin and the peers pick theirs, and
+    // any clashes are sorted out the same way everywhere
+    let mut remote_skins = result.remote_skins.iter();
+    let picks: Vec<String> = players
+        .iter()
+        .map(|player| match player {
+            PlayerSlot::Local => skin.to_string(),
+            PlayerSlot::Remote(_) => remote_skins.next().unwrap().clone(),
         })
+        .collect();
+
+    return Ok(Match {
+        skins: palettes.resolve(&picks),
+        presentation: Presentation::new(&game.state),
+        game,
+        effects: Effects::new(),
+        sess,
+        // time variables for tick rate
+        last_update: Instant::now(),
+        accumulator: Duration::ZERO,
+        training,
+        info_text: get_input_delay_text(result),
+        show_debug: false,
+    });
 }
 
-struct Esport {
-    game: Game,
-    resources: Resources,
-    sess: P2PSession<GGRSConfig>,
-    last_update: Instant,
-    accumulator: Duration,
-    scaler: ScreenScaler,
+impl Connecting {
+    fn update(&mut self, palettes: &Palettes) -> Option<Scene> {
+        let handshake = self.handshake.as_mut()?;
+        match handshake.poll() {
+            HandshakeStatus::Done(result) => {
+                // the session binds the same port the handshake used
+                self.handshake = None;
+                let game = self.game.take().unwrap();
+                return Some(
+                    match start_match(
+                        game,
+                        &self.players,
+                        &result,
+                        None,
+                        &self.skin,
+                        palettes,
+                    ) {
+                        Ok(new_match) => Scene::Match(new_match),
+                        Err(message) => {
+                            let mut menu = Menu::new();
+                            menu.set_message(&message);
+                            Scene::Menu(menu)
+                        }
+                    },
+                );
+            }
+            HandshakeStatus::Failed(reason) => {
+                println!("{}", reason);
+                let mut menu = Menu::new();
+                menu.set_message(&reason);
+                return Some(Scene::Menu(menu));
+            }
+            HandshakeStatus::Measuring => {
+                self.status = if handshake.is_waiting_for_peers() {
+                    "WAITING FOR PEERS".to_string()
+                } else {
+                    format!("MEASURING PING {}MS", handshake.ping_ms())
+                };
+            }
+            HandshakeStatus::Exchanging => {
+                self.status = "STARTING MATCH".to_string();
+            }
+        }
+        return None;
+    }
 }
 
-impl Esport {
+impl Match {
     fn draw_player(
         &self,
         player: &Player,
+        position: Vec2<f32>,
         texture: &Texture,
         sprite: &Sprite,
         ctx: &mut Context,
     ) {
-        //let simple = Mesh::rectangle(
-        //ctx,
-        //ShapeStyle::Fill,
-        //Rectangle {
-        //x: 0.0,
-        //y: 0.0,
-        //width: 6.0,
-        //height: 12.0,
-        //},
-        //)
-        //.unwrap();
-        //simple.draw(
-        //ctx,
-        //Vec2::new(
-        //world_to_screen(player.hitbox.x),
-        //world_to_screen(player.hitbox.y),
-        //),
-        //);
-
         if player.is_dead {
             return;
         }
+        // blink while invulnerable after respawning
+        if player.invulnerable_timer > 0
+            && player.invulnerable_timer / 4 % 2 == 0
+        {
+            return;
+        }
 
         let mut current_frame = player.current_animation_frame;
-        current_frame = current_frame
-            / sprite.animations[&player.current_animation].fps;
-        current_frame = current_frame
-            % sprite.animations[&player.current_animation].frames.len();
+        current_frame /= sprite.animations[&player.current_animation].fps;
+        current_frame %=
+            sprite.animations[&player.current_animation].frames.len();
         let scale_x = if player.is_facing_left { -1.0 } else { 1.0 };
         let color = if player.dodge_timer > 0 {
             Color::BLACK
@@ -174,27 +503,57 @@ impl Esport {
                 sprite.frame_width as f32,
                 sprite.frame_height as f32,
             ),
+            DrawParams::new()
+                .position(position)
+                .origin(Vec2::new(
+                    sprite.frame_width as f32 / 2.0,
+                    sprite.frame_height as f32 / 2.0,
+                ))
+                .scale(Vec2::new(scale_x, 1.0))
+                .color(color),
+        );
+    }
+
+    fn draw_afterimage(
+        &self,
+        afterimage: &Afterimage,
+        resources: &Resources,
+        ctx: &mut Context,
+    ) {
+        let texture = &resources.textures[&afterimage.sprite];
+        let sprite = &resources.sprites[&afterimage.sprite];
+        let animation = &sprite.animations[&afterimage.animation];
+        let current_frame = afterimage.animation_frame / animation.fps
+            % animation.frames.len();
+        let scale_x = if afterimage.is_facing_left { -1.0 } else { 1.0 };
+        let opacity =
+            0.5 * (1.0 - afterimage.age as f32 / AFTERIMAGE_FRAMES as f32);
+        texture.draw_region(
+            ctx,
+            Rectangle::new(
+                animation.frames[current_frame].x as f32,
+                animation.frames[current_frame].y as f32,
+                sprite.frame_width as f32,
+                sprite.frame_height as f32,
+            ),
             DrawParams::new()
                 .position(Vec2::new(
-                    world_to_screen(
-                        player.hitbox.x + player.hitbox.width / 2,
-                    ),
-                    world_to_screen(
-                        player.hitbox.y + player.hitbox.height / 2,
-                    ),
+                    world_to_screen(afterimage.center.x),
+                    world_to_screen(afterimage.center.y),
                 ))
                 .origin(Vec2::new(
                     sprite.frame_width as f32 / 2.0,
                     sprite.frame_height as f32 / 2.0,
                 ))
                 .scale(Vec2::new(scale_x, 1.0))
-                .color(color),
+                .color(Color::rgba(1.0, 1.0, 1.0, opacity)),
         );
     }
 
     fn draw_boomerang(
         &self,
         boomerang: &Boomerang,
+        position: Vec2<f32>,
         texture: &Texture,
         sprite: &Sprite,
         ctx: &mut Context,
@@ -203,10 +562,10 @@ impl Esport {
             return;
         }
         let mut current_frame = boomerang.current_animation_frame;
-        current_frame = current_frame
-            / sprite.animations[&boomerang.current_animation].fps;
-        current_frame = current_frame
-            % sprite.animations[&boomerang.current_animation].frames.len();
+        current_frame /=
+            sprite.animations[&boomerang.current_animation].fps;
+        current_frame %=
+            sprite.animations[&boomerang.current_animation].frames.len();
         texture.draw_region(
             ctx,
             Rectangle::new(
@@ -219,22 +578,55 @@ impl Esport {
                 sprite.frame_width as f32,
                 sprite.frame_height as f32,
             ),
-            DrawParams::new()
-                .position(Vec2::new(
-                    world_to_screen(
-                        boomerang.hitbox.x + boomerang.hitbox.width / 2,
-                    ),
-                    world_to_screen(
-                        boomerang.hitbox.y + boomerang.hitbox.height / 2,
-                    ),
-                ))
-                .origin(Vec2::new(
-                    sprite.frame_width as f32 / 2.0,
-                    sprite.frame_height as f32 / 2.0,
-                )),
+            DrawParams::new().position(position).origin(Vec2::new(
+                sprite.frame_width as f32 / 2.0,
+                sprite.frame_height as f32 / 2.0,
+            )),
         );
     }
 
+    // a pip above the player's head for each charge level, lit up as
+    // the throw charges and flashing once it is full
+    fn draw_charge(
+        &self,
+        player: &Player,
+        position: Vec2<f32>,
+        boomerang: &Boomerang,
+        resources: &Resources,
+        settings: &Settings,
+        ctx: &mut Context,
+    ) {
+        if player.is_dead || !boomerang.is_charging {
+            return;
+        }
+        let ruleset = &self.game.ruleset;
+        let charge_level = boomerang.charge_level(ruleset);
+        let is_full = charge_level == ruleset.boomerang_charge_levels;
+        let width = ruleset.boomerang_charge_levels as f32 * 3.0 - 1.0;
+        let left = position.x - width / 2.0;
+        let top =
+            position.y - world_to_screen(player.hitbox.height / 2) - 5.0;
+        for level in 0..ruleset.boomerang_charge_levels {
+            let color = if level >= charge_level {
+                Color::rgba(0.0, 0.0, 0.0, 0.5)
+            } else if is_full
+                && (!settings.screen_effects
+                    || self.game.state.frame / 4 % 2 == 0)
+            {
+                Color::rgb(1.0, 0.9, 0.35)
+            } else {
+                Color::WHITE
+            };
+            resources.rectangle.draw(
+                ctx,
+                DrawParams::new()
+                    .position(Vec2::new(left + level as f32 * 3.0, top))
+                    .scale(Vec2::new(2.0, 2.0))
+                    .color(color),
+            );
+        }
+    }
+
     fn draw_tiles(
         &self,
         level: &Level,
@@ -263,18 +655,18 @@ impl Esport {
         sprite: &Sprite,
         ctx: &mut Context,
     ) {
-        if particle.current_animation == "none".to_string() {
+        if particle.current_animation == "none" {
             return;
         }
         let mut scale = 1.0;
-        if particle.current_animation == "simple".to_string() {
+        if particle.current_animation == "simple" {
             scale = 2.0;
         }
         let mut current_frame = particle.current_animation_frame;
-        current_frame = current_frame
-            / sprite.animations[&particle.current_animation].fps;
-        current_frame = current_frame
-            % sprite.animations[&particle.current_animation].frames.len();
+        current_frame /=
+            sprite.animations[&particle.current_animation].fps;
+        current_frame %=
+            sprite.animations[&particle.current_animation].frames.len();
         texture.draw_region(
             ctx,
             Rectangle::new(
@@ -300,30 +692,103 @@ impl Esport {
         );
     }
 
-    fn draw_curtain(&self, ctx: &mut Context) {
-        let simple = Mesh::rectangle(
-            ctx,
-            ShapeStyle::Fill,
-            Rectangle {
-                x: 0.0,
-                y: 0.0,
-                width: 320.0,
-                height: 180.0,
-            },
-        )
-        .unwrap();
-        let opacity = self.game.state.curtain.opacity as f32 / MAX_OPACITY as f32 ;
-        simple.draw(
+    fn draw_curtain(&self, ctx: &mut Context, resources: &Resources) {
+        let curtain = &self.game.state.curtain;
+        let coverage = curtain.coverage();
+        if coverage == 0.0 {
+            return;
+        }
+        let params = match curtain.transition {
+            Transition::Fade => DrawParams::new()
+                .scale(Vec2::new(320.0, 180.0))
+                .color(Color::rgba(0.0, 0.0, 0.0, coverage)),
+            // closes from the left and opens towards the right
+            Transition::Wipe => {
+                let left = if curtain.is_closing {
+                    0.0
+                } else {
+                    320.0 * (1.0 - coverage)
+                };
+                DrawParams::new()
+                    .position(Vec2::new(left, 0.0))
+                    .scale(Vec2::new(320.0 * coverage, 180.0))
+                    .color(Color::BLACK)
+            }
+            Transition::Iris => {
+                let radius = IRIS_MAX_RADIUS * (1.0 - coverage);
+                if radius >= 1.0 {
+                    resources.iris.draw(
+                        ctx,
+                        DrawParams::new()
+                            .position(Vec2::new(
+                                world_to_screen(curtain.center.x),
+                                world_to_screen(curtain.center.y),
+                            ))
+                            .scale(Vec2::new(radius, radius))
+                            .color(Color::BLACK),
+                    );
+                    return;
+                }
+                DrawParams::new()
+                    .scale(Vec2::new(320.0, 180.0))
+                    .color(Color::BLACK)
+            }
+        };
+        resources.rectangle.draw(ctx, params);
+    }
+
+    // a white flash over everything when someone dies
+    fn draw_flash(&self, ctx: &mut Context, resources: &Resources) {
+        if self.effects.flash_frames == 0 {
+            return;
+        }
+        let opacity =
+            self.effects.flash_frames as f32 / FLASH_FRAMES as f32 * 0.6;
+        resources.rectangle.draw(
             ctx,
-            //Vec2::new(0.0, 0.0),
             DrawParams::new()
-                .position(Vec2::new(0.0, 0.0))
-                .color(Color::rgba(0.0, 0.0, 0.0, opacity))
+                .scale(Vec2::new(320.0, 180.0))
+                .color(Color::rgba(1.0, 1.0, 1.0, opacity)),
         );
     }
 
-    fn handle_sounds(&mut self) {
-        for player_num in 0..2 {
+    // trails behind dodging players and thrown boomerangs
+    fn add_afterimages(&mut self) {
+        let state = &self.game.state;
+        for (player_num, player) in state.players.iter().enumerate() {
+            if !player.is_dead
+                && player.dodge_timer > 0
+                && state.frame % 2 == 0
+            {
+                self.effects.add_afterimage(
+                    &format!("player_{}", self.skins[player_num]),
+                    &player.current_animation,
+                    player.current_animation_frame,
+                    IntVector2D {
+                        x: player.center_x(),
+                        y: player.center_y(),
+                    },
+                    player.is_facing_left,
+                );
+            }
+            let boomerang = &state.boomerangs[player_num];
+            if boomerang.is_in_flight() {
+                self.effects.add_afterimage(
+                    &format!("boomerang_{}", self.skins[player_num]),
+                    &boomerang.current_animation,
+                    boomerang.current_animation_frame,
+                    IntVector2D {
+                        x: boomerang.center_x(),
+                        y: boomerang.center_y(),
+                    },
+                    false,
+                );
+            }
+        }
+    }
+
+    fn handle_sounds(&mut self, resources: &Resources, sfx_volume: f32) {
+        for player_num in 0..self.game.state.players.len() {
             for _ in
                 0..self.game.state.players[player_num].sound_commands.len()
             {
@@ -331,7 +796,12 @@ impl Esport {
                     .sound_commands
                     .pop()
                     .unwrap();
-                self.handle_sound_command(player_num, &sound_command);
+                handle_sound_command(
+                    resources,
+                    sfx_volume,
+                    player_num,
+                    &sound_command,
+                );
             }
             for _ in 0..self.game.state.boomerangs[player_num]
                 .sound_commands
@@ -341,45 +811,39 @@ impl Esport {
                     .sound_commands
                     .pop()
                     .unwrap();
-                self.handle_sound_command(player_num, &sound_command);
+                handle_sound_command(
+                    resources,
+                    sfx_volume,
+                    player_num,
+                    &sound_command,
+                );
             }
         }
     }
 
-    fn handle_sound_command(
+    // returns a message if the match ended on its own
+    fn update(
         &mut self,
-        player_num: usize,
-        sound_command: &(String, String, i32),
-    ) {
-        let sound = &self.resources.sounds
-            [&get_player_sound_name(player_num, &sound_command.0)];
-        let volume: f32 = sound_command.2 as f32 / 100.0;
-        if sound_command.1 == "play".to_string() {
-            sound.stop();
-            sound.set_volume(volume);
-            sound.play();
-        } else if sound_command.1 == "loop".to_string() {
-            sound.set_repeating(true);
-            sound.set_volume(volume);
-            sound.play();
-        } else if sound_command.1 == "stop".to_string() {
-            sound.stop();
+        ctx: &mut Context,
+        resources: &Resources,
+        settings: &Settings,
+    ) -> Option<String> {
+        if input::is_key_pressed(ctx, Key::F3) {
+            self.show_debug = !self.show_debug;
+        }
+        if let Some(training) = &mut self.training {
+            training.update(ctx, &mut self.game);
         }
-    }
-}
-
-fn get_player_sound_name(player_num: usize, sound_name: &str) -> String {
-    return format!("player{}-{}", player_num, sound_name);
-}
 
-impl State for Esport {
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
         // communicate, receive and send packets
         self.sess.poll_remote_clients();
 
         // print GGRS events
         for event in self.sess.events() {
             println!("Event: {:?}", event);
+            if let GGRSEvent::Disconnected { .. } = event {
+                return Some("peer disconnected".to_string());
+            }
         }
 
         // this is to keep ticks between clients synchronized.
@@ -407,12 +871,13 @@ impl State for Esport {
             if self.sess.current_state() == SessionState::Running {
                 // add input for all local players
                 for handle in self.sess.local_player_handles() {
-                    self.sess
-                        .add_local_input(
-                            handle,
-                            self.game.local_input(ctx, handle),
-                        )
-                        .unwrap();
+                    let input = match &mut self.training {
+                        Some(training) => {
+                            training.input(ctx, &self.game, handle)
+                        }
+                        None => self.game.local_input(ctx, handle),
+                    };
+                    self.sess.add_local_input(handle, input).unwrap();
                 }
 
                 match self.sess.advance_frame() {
@@ -428,82 +893,302 @@ impl State for Esport {
                     }
                 }
 
-                self.handle_sounds()
+                let (rollback_frame, effects) = self.game.take_effects();
+                self.presentation
+                    .advance(&self.game.state, rollback_frame.is_some());
+                self.effects.sync(rollback_frame, effects);
+                self.effects.advance();
+                self.add_afterimages();
+
+                self.handle_sounds(resources, settings.sfx_volume())
             }
         }
 
-        Ok(())
+        // how far into the next frame this draw is
+        self.presentation.set_alpha(
+            (self.accumulator.as_secs_f64() / fps_delta) as f32,
+        );
+
+        return None;
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
-        graphics::set_canvas(ctx, self.scaler.canvas());
+    fn draw(
+        &self,
+        ctx: &mut Context,
+        resources: &mut Resources,
+        settings: &Settings,
+    ) {
         graphics::clear(ctx, Color::rgb(0.392, 0.584, 0.929));
 
         self.draw_tiles(
-            &self.game.level,
-            &self.resources.textures["tile"],
+            &self.game.state.level,
+            &resources.textures["tile"],
             ctx,
         );
 
-        self.draw_boomerang(
-            &self.game.state.boomerangs[0],
-            &self.resources.textures["boomerang_one"],
-            &self.resources.sprites["boomerang_one"],
-            ctx,
-        );
-        self.draw_boomerang(
-            &self.game.state.boomerangs[1],
-            &self.resources.textures["boomerang_two"],
-            &self.resources.sprites["boomerang_two"],
-            ctx,
-        );
+        for afterimage in &self.effects.afterimages {
+            self.draw_afterimage(afterimage, resources, ctx);
+        }
 
-        self.draw_player(
-            &self.game.state.players[0],
-            &self.resources.textures["player_one"],
-            &self.resources.sprites["player_one"],
-            ctx,
-        );
-        self.draw_player(
-            &self.game.state.players[1],
-            &self.resources.textures["player_two"],
-            &self.resources.sprites["player_two"],
-            ctx,
-        );
+        for (player_num, boomerang) in
+            self.game.state.boomerangs.iter().enumerate()
+        {
+            let name = format!("boomerang_{}", self.skins[player_num]);
+            self.draw_boomerang(
+                boomerang,
+                self.presentation.boomerang_position(player_num),
+                &resources.textures[&name],
+                &resources.sprites[&name],
+                ctx,
+            );
+        }
 
-        for particle in &self.game.state.particles {
+        for (player_num, player) in
+            self.game.state.players.iter().enumerate()
+        {
+            let name = format!("player_{}", self.skins[player_num]);
+            let position = self.presentation.player_position(player_num);
+            self.draw_player(
+                player,
+                position,
+                &resources.textures[&name],
+                &resources.sprites[&name],
+                ctx,
+            );
+            self.draw_charge(
+                player,
+                position,
+                &self.game.state.boomerangs[player_num],
+                resources,
+                settings,
+                ctx,
+            );
+        }
+
+        for particle in self.effects.particles.iter() {
             self.draw_particle(
                 particle,
-                &self.resources.textures["particle"],
-                &self.resources.sprites["particle"],
+                &resources.textures["particle"],
+                &resources.sprites["particle"],
                 ctx,
             );
         }
 
-        let bounds = self.resources.round_start.get_bounds(ctx).unwrap();
-        //self.resources.round_start.set_content("FIGHT");
-        self.resources.round_start.draw(
-            ctx,
-            Vec2::new(
-                160.0 - bounds.width / 2.0,
-                90.0 - bounds.height / 2.0,
-            ),
-        );
+        if settings.screen_effects {
+            self.draw_flash(ctx, resources);
+        }
+
+        if self.show_debug {
+            debug::draw(ctx, resources, self);
+        }
+
+        hud::draw(ctx, resources, self);
+
+        self.draw_curtain(ctx, resources);
+    }
+}
+
+fn handle_sound_command(
+    resources: &Resources,
+    sfx_volume: f32,
+    player_num: usize,
+    sound_command: &(String, String, i32),
+) {
+    let sound = &resources.sounds
+        [&get_player_sound_name(player_num, &sound_command.0)];
+    let volume: f32 = sound_command.2 as f32 / 100.0 * sfx_volume;
+    if sound_command.1 == "play" {
+        sound.stop();
+        sound.set_volume(volume);
+        sound.play();
+    } else if sound_command.1 == "loop" {
+        sound.set_repeating(true);
+        sound.set_volume(volume);
+        sound.play();
+    } else if sound_command.1 == "stop" {
+        sound.stop();
+    }
+}
+
+fn get_input_delay_text(result: &HandshakeResult) -> String {
+    let mut text = format!("INPUT DELAY {}", result.input_delay);
+    for remote_input_delay in &result.remote_input_delays {
+        text.push_str(&format!(" / {}", remote_input_delay));
+    }
+    if !result.remote_input_delays.is_empty() {
+        text.push_str(&format!("  PING {}MS", result.ping_ms));
+    }
+    return text;
+}
 
-        self.draw_curtain(ctx);
+fn get_player_sound_name(player_num: usize, sound_name: &str) -> String {
+    return format!("player{}-{}", player_num, sound_name);
+}
+
+impl State for Esport {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        let mut apply_settings = false;
+        let next_scene = match &mut self.scene {
+            Scene::Menu(menu) => {
+                match menu.update(ctx, &self.resources, &mut self.settings)
+                {
+                    MenuAction::None => None,
+                    MenuAction::SettingsChanged => {
+                        apply_settings = true;
+                        None
+                    }
+                    MenuAction::Host {
+                        local_port,
+                        input_delay,
+                    } => Some(start_connecting(
+                        local_port,
+                        vec![PlayerSlot::Local, PlayerSlot::Remote(None)],
+                        input_delay,
+                        &self.settings.ruleset,
+                        &self.settings.skin,
+                    )),
+                    MenuAction::Join {
+                        host_addr,
+                        local_port,
+                        input_delay,
+                    } => Some(start_connecting(
+                        local_port,
+                        vec![
+                            PlayerSlot::Remote(Some(host_addr)),
+                            PlayerSlot::Local,
+                        ],
+                        input_delay,
+                        &self.settings.ruleset,
+                        &self.settings.skin,
+                    )),
+                    MenuAction::Local { num_players } => {
+                        Some(start_offline(
+                            num_players,
+                            false,
+                            &self.settings.ruleset,
+                            &self.settings.skin,
+                            &self.resources.palettes,
+                        ))
+                    }
+                    MenuAction::Training => Some(start_offline(
+                        MIN_PLAYERS,
+                        true,
+                        &self.settings.ruleset,
+                        &self.settings.skin,
+                        &self.resources.palettes,
+                    )),
+                    MenuAction::Quit => {
+                        window::quit(ctx);
+                        None
+                    }
+                }
+            }
+            Scene::Connecting(connecting) => {
+                if input::is_key_pressed(ctx, Key::Escape) {
+                    Some(Scene::Menu(Menu::new()))
+                } else {
+                    connecting.update(&self.resources.palettes)
+                }
+            }
+            Scene::Match(current_match) => {
+                if input::is_key_pressed(ctx, Key::Escape) {
+                    Some(Scene::Menu(Menu::new()))
+                } else {
+                    current_match
+                        .update(ctx, &self.resources, &self.settings)
+                        .map(|message| {
+                            let mut menu = Menu::new();
+                            menu.set_message(&message);
+                            Scene::Menu(menu)
+                        })
+                }
+            }
+        };
+        if let Some(next_scene) = next_scene {
+            self.scene = next_scene;
+        }
+        if apply_settings {
+            self.apply_settings(ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
+        graphics::set_canvas(ctx, self.scaler.canvas());
+
+        match &self.scene {
+            Scene::Menu(menu) => {
+                graphics::clear(ctx, Color::BLACK);
+                menu.draw(
+                    ctx,
+                    &mut self.resources.menu_text,
+                    &self.settings,
+                );
+            }
+            Scene::Connecting(connecting) => {
+                graphics::clear(ctx, Color::BLACK);
+                menu::draw_lines(
+                    ctx,
+                    &mut self.resources.menu_text,
+                    &[
+                        (&connecting.status, Color::WHITE),
+                        ("", Color::WHITE),
+                        ("ESCAPE TO CANCEL", Color::WHITE),
+                    ],
+                );
+            }
+            Scene::Match(current_match) => {
+                current_match.draw(
+                    ctx,
+                    &mut self.resources,
+                    &self.settings,
+                );
+            }
+        }
 
         graphics::reset_canvas(ctx);
         graphics::clear(ctx, Color::BLACK);
 
+        // shake the whole scaled screen, so it moves in whole window
+        // pixels and the edges show the border
+        let shake = match &self.scene {
+            Scene::Match(current_match)
+                if self.settings.screen_effects =>
+            {
+                current_match.effects.shake_offset()
+            }
+            _ => IntVector2D { x: 0, y: 0 },
+        };
+        let offset = self.scaler.unproject(Vec2::new(
+            world_to_screen(shake.x),
+            world_to_screen(shake.y),
+        )) - self.scaler.unproject(Vec2::new(0.0, 0.0));
+        graphics::set_transform_matrix(
+            ctx,
+            Mat4::translation_2d(offset.round()),
+        );
         self.scaler.draw(ctx);
+        graphics::reset_transform_matrix(ctx);
 
         Ok(())
     }
 
-    fn event(&mut self, _: &mut Context, event: Event) -> tetra::Result {
+    fn event(&mut self, ctx: &mut Context, event: Event) -> tetra::Result {
         if let Event::Resized { width, height } = event {
             self.scaler.set_outer_size(width, height);
         }
+        if let Event::KeyPressed { key: Key::Enter } = event {
+            if input::is_key_modifier_down(ctx, KeyModifier::Alt) {
+                self.settings.fullscreen = !self.settings.fullscreen;
+                self.apply_settings(ctx)?;
+            }
+        }
+        if let Event::TextInput { text } = &event {
+            if let Scene::Menu(menu) = &mut self.scene {
+                menu.text_input(text);
+            }
+        }
 
         Ok(())
     }
@@ -561,22 +1246,22 @@ struct Resources {
     textures: HashMap<String, Texture>,
     sprites: HashMap<String, Sprite>,
     sounds: HashMap<String, SoundInstance>,
-    round_start: Text,
+    font: PixelFont,
+    menu_text: Text,
+    palettes: Palettes,
+    // shapes that are drawn every frame, built once and scaled to fit
+    rectangle: Mesh,
+    iris: Mesh,
 }
 
 impl Resources {
     pub fn new(ctx: &mut Context) -> Self {
+        let texture_names =
+            vec!["tile".to_string(), "particle".to_string()];
         let mut textures: HashMap<String, Texture> = HashMap::new();
-        for name in [
-            "player_one",
-            "player_two",
-            "tile",
-            "boomerang_one",
-            "boomerang_two",
-            "particle",
-        ] {
+        for name in texture_names {
             textures.insert(
-                name.to_string(),
+                name.clone(),
                 Texture::new(
                     ctx,
                     format!("./resources/graphics/{}.png", name),
@@ -585,27 +1270,46 @@ impl Resources {
             );
         }
 
-        let mut player_one_sprite =
-            Sprite::new(textures["player_one"].width(), 8, 12);
-        let mut player_two_sprite =
-            Sprite::new(textures["player_two"].width(), 8, 12);
-        for sprite in [&mut player_one_sprite, &mut player_two_sprite] {
+        // every skin is a recoloring of the first player's graphics
+        let palettes = Palettes::load().unwrap();
+        let player_image =
+            ImageData::new("./resources/graphics/player_one.png").unwrap();
+        let boomerang_image =
+            ImageData::new("./resources/graphics/boomerang_one.png")
+                .unwrap();
+        for skin in &palettes.skins {
+            textures.insert(
+                format!("player_{}", skin.name),
+                palettes
+                    .recolor_player(&player_image, skin)
+                    .to_texture(ctx)
+                    .unwrap(),
+            );
+            textures.insert(
+                format!("boomerang_{}", skin.name),
+                palettes
+                    .recolor_boomerang(&boomerang_image, skin)
+                    .to_texture(ctx)
+                    .unwrap(),
+            );
+        }
+
+        let mut sprites: HashMap<String, Sprite> = HashMap::new();
+        for skin_name in palettes.names() {
+            let name = format!("player_{}", skin_name);
+            let mut sprite = Sprite::new(textures[&name].width(), 8, 12);
             sprite.add("idle".to_string(), &[0], 1);
             sprite.add("run".to_string(), &[1, 2, 3, 2], 8);
             sprite.add("jump".to_string(), &[4], 1);
             sprite.add("wall".to_string(), &[5], 1);
             sprite.add("skid".to_string(), &[6], 1);
             sprite.add("slide".to_string(), &[7], 1);
-        }
+            sprites.insert(name, sprite);
 
-        let mut boomerang_one_sprite =
-            Sprite::new(textures["boomerang_one"].width(), 8, 8);
-        let mut boomerang_two_sprite =
-            Sprite::new(textures["boomerang_two"].width(), 8, 8);
-        for sprite in
-            [&mut boomerang_one_sprite, &mut boomerang_two_sprite]
-        {
+            let name = format!("boomerang_{}", skin_name);
+            let mut sprite = Sprite::new(textures[&name].width(), 8, 8);
             sprite.add("idle".to_string(), &[0], 1);
+            sprites.insert(name, sprite);
         }
 
         let mut particle_sprite =
@@ -631,19 +1335,15 @@ impl Resources {
             SIMPLE_ANIMATION_SPEED,
         );
 
-        let sprites = HashMap::from([
-            ("player_one".to_string(), player_one_sprite),
-            ("player_two".to_string(), player_two_sprite),
-            ("boomerang_one".to_string(), boomerang_one_sprite),
-            ("boomerang_two".to_string(), boomerang_two_sprite),
-            ("particle".to_string(), particle_sprite),
-        ]);
+        sprites.insert("particle".to_string(), particle_sprite);
 
         let mut sounds: HashMap<String, SoundInstance> = HashMap::new();
         for name in [
             //"addfinalpoint",
             //"addpoint",
+            "bounce",
             "catch",
+            "clash",
             "death",
             "dodge",
             //"dodge1",
@@ -655,9 +1355,8 @@ impl Resources {
             //"gameover",
             "jump",
             "land",
-            //"menuselect",
-            //"menustart",
-            //"ready",
+            "parry",
+            "ready",
             "run",
             //"showscoreboard",
             "skid",
@@ -666,7 +1365,7 @@ impl Resources {
             "wallslide",
             "whoosh",
         ] {
-            for player_num in 0..2 {
+            for player_num in 0..MAX_PLAYERS {
                 sounds.insert(
                     format!("player{}-{}", player_num, name),
                     Sound::new(format!("./resources/audio/{}.wav", name))
@@ -677,17 +1376,60 @@ impl Resources {
             }
         }
 
-        let round_start = Text::new(
-            "READY",
-            Font::vector(ctx, "./resources/fonts/arialbold.ttf", 64.0)
+        for name in ["menuselect", "menustart"] {
+            sounds.insert(
+                name.to_string(),
+                Sound::new(format!("./resources/audio/{}.wav", name))
+                    .unwrap()
+                    .spawn(ctx)
+                    .unwrap(),
+            );
+        }
+
+        let menu_text = Text::new(
+            "",
+            Font::vector(ctx, "./resources/fonts/arialbold.ttf", 12.0)
                 .unwrap(),
         );
 
+        let rectangle = Mesh::rectangle(
+            ctx,
+            ShapeStyle::Fill,
+            Rectangle::new(0.0, 0.0, 1.0, 1.0),
+        )
+        .unwrap();
+
+        // a ring around a hole of radius 1, wide enough to cover the
+        // screen from anywhere on it down to a hole of a pixel
+        let mut iris = GeometryBuilder::new();
+        for segment in 0..IRIS_SEGMENTS {
+            let point = |segment: i32, radius: f32| {
+                let angle = segment as f32 / IRIS_SEGMENTS as f32
+                    * std::f32::consts::TAU;
+                return Vec2::new(angle.cos(), angle.sin()) * radius;
+            };
+            iris.polygon(
+                ShapeStyle::Fill,
+                &[
+                    point(segment, 1.0),
+                    point(segment, IRIS_MAX_RADIUS),
+                    point(segment + 1, IRIS_MAX_RADIUS),
+                    point(segment + 1, 1.0),
+                ],
+            )
+            .unwrap();
+        }
+        let iris = iris.build_mesh(ctx).unwrap();
+
         Self {
             textures,
             sprites,
             sounds,
-            round_start,
+            font: PixelFont::new(ctx),
+            menu_text,
+            palettes,
+            rectangle,
+            iris,
         }
     }
 }
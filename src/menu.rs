@@ -0,0 +1,472 @@
+use std::net::SocketAddr;
+use tetra::graphics::text::Text;
+use tetra::graphics::Color;
+use tetra::input::{self, GamepadButton, Key, KeyModifier};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::game::{MAX_PLAYERS, MIN_PLAYERS};
+use crate::handshake::MAX_INPUT_DELAY;
+use crate::ruleset::Ruleset;
+use crate::settings::{
+    Settings, MAX_VOLUME, MAX_WINDOW_SCALE, VOLUME_STEP,
+};
+use crate::Resources;
+
+pub const DEFAULT_HOST_PORT: u16 = 7000;
+pub const DEFAULT_JOIN_PORT: u16 = 7001;
+
+const LINE_HEIGHT: f32 = 14.0;
+const SELECTED_COLOR: Color = Color::rgb(1.0, 0.9, 0.35);
+const MAX_FIELD_LENGTH: usize = 21;
+
+pub enum MenuAction {
+    None,
+    Host {
+        local_port: u16,
+        input_delay: Option<usize>,
+    },
+    Join {
+        host_addr: SocketAddr,
+        local_port: u16,
+        input_delay: Option<usize>,
+    },
+    Local {
+        num_players: usize,
+    },
+    Training,
+    // the settings were edited and should be applied and saved
+    SettingsChanged,
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Screen {
+    Main,
+    Host,
+    Join,
+    Local,
+    Settings,
+}
+
+pub struct Menu {
+    screen: Screen,
+    selection: usize,
+    host_port: String,
+    join_address: String,
+    join_port: String,
+    local_players: usize,
+    message: String,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        return Menu {
+            screen: Screen::Main,
+            selection: 0,
+            host_port: DEFAULT_HOST_PORT.to_string(),
+            join_address: String::new(),
+            join_port: DEFAULT_JOIN_PORT.to_string(),
+            local_players: MIN_PLAYERS,
+            message: String::new(),
+        };
+    }
+
+    // shown at the bottom of the menu, e.g. why a connection failed
+    pub fn set_message(&mut self, message: &str) {
+        self.message = message.to_uppercase();
+    }
+
+    fn items(&self, settings: &Settings) -> Vec<String> {
+        return match self.screen {
+            Screen::Main => vec![
+                "HOST".to_string(),
+                "JOIN".to_string(),
+                "LOCAL".to_string(),
+                "TRAINING".to_string(),
+                "SETTINGS".to_string(),
+                "QUIT".to_string(),
+            ],
+            Screen::Host => vec![
+                format!("PORT: {}", self.host_port),
+                skin_item(settings),
+                "START HOSTING".to_string(),
+                "BACK".to_string(),
+            ],
+            Screen::Join => vec![
+                format!("HOST ADDRESS: {}", self.join_address),
+                format!("LOCAL PORT: {}", self.join_port),
+                skin_item(settings),
+                "CONNECT".to_string(),
+                "BACK".to_string(),
+            ],
+            Screen::Local => vec![
+                format!("PLAYERS: < {} >", self.local_players),
+                skin_item(settings),
+                "START".to_string(),
+                "BACK".to_string(),
+            ],
+            Screen::Settings => vec![
+                match settings.input_delay {
+                    Some(delay) => format!("INPUT DELAY: < {} >", delay),
+                    None => "INPUT DELAY: < AUTO >".to_string(),
+                },
+                format!(
+                    "RULESET: < {} >",
+                    settings.ruleset.to_uppercase()
+                ),
+                format!(
+                    "WINDOW MODE: < {} >",
+                    if settings.fullscreen {
+                        "FULLSCREEN"
+                    } else {
+                        "WINDOWED"
+                    }
+                ),
+                format!("WINDOW SCALE: < {}X >", settings.window_scale),
+                format!(
+                    "INTEGER SCALING: < {} >",
+                    on_off(settings.integer_scaling)
+                ),
+                format!("VSYNC: < {} >", on_off(settings.vsync)),
+                format!("MASTER VOLUME: < {} >", settings.master_volume),
+                format!("SFX VOLUME: < {} >", settings.sfx_volume),
+                format!("MUSIC VOLUME: < {} >", settings.music_volume),
+                format!(
+                    "SHAKE & FLASHES: < {} >",
+                    on_off(settings.screen_effects)
+                ),
+                "BACK".to_string(),
+            ],
+        };
+    }
+
+    fn title(&self) -> &str {
+        return match self.screen {
+            Screen::Main => "ESPORT HEAVEN ONLINE",
+            Screen::Host => "HOST",
+            Screen::Join => "JOIN",
+            Screen::Local => "LOCAL",
+            Screen::Settings => "SETTINGS",
+        };
+    }
+
+    fn is_skin_selected(&self) -> bool {
+        return matches!(
+            (self.screen, self.selection),
+            (Screen::Host, 1) | (Screen::Join, 2) | (Screen::Local, 1)
+        );
+    }
+
+    // the text field that typing goes into, if one is selected
+    fn selected_field(&mut self) -> Option<&mut String> {
+        return match (self.screen, self.selection) {
+            (Screen::Host, 0) => Some(&mut self.host_port),
+            (Screen::Join, 0) => Some(&mut self.join_address),
+            (Screen::Join, 1) => Some(&mut self.join_port),
+            _ => None,
+        };
+    }
+
+    pub fn text_input(&mut self, text: &str) {
+        if let Some(field) = self.selected_field() {
+            for c in text.chars() {
+                if (c.is_ascii_digit() || c == '.' || c == ':')
+                    && field.len() < MAX_FIELD_LENGTH
+                {
+                    field.push(c);
+                }
+            }
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        ctx: &mut Context,
+        resources: &Resources,
+        settings: &mut Settings,
+    ) -> MenuAction {
+        let num_items = self.items(settings).len();
+        if input::is_key_pressed(ctx, Key::Down)
+            || input::is_gamepad_button_pressed(
+                ctx,
+                0,
+                GamepadButton::Down,
+            )
+        {
+            self.selection = (self.selection + 1) % num_items;
+            play_sound(resources, settings, "menuselect");
+        }
+        if input::is_key_pressed(ctx, Key::Up)
+            || input::is_gamepad_button_pressed(ctx, 0, GamepadButton::Up)
+        {
+            self.selection = (self.selection + num_items - 1) % num_items;
+            play_sound(resources, settings, "menuselect");
+        }
+        if input::is_key_pressed(ctx, Key::Backspace) {
+            if let Some(field) = self.selected_field() {
+                field.pop();
+            }
+        }
+        let mut direction = 0;
+        if input::is_key_pressed(ctx, Key::Left)
+            || input::is_gamepad_button_pressed(
+                ctx,
+                0,
+                GamepadButton::Left,
+            )
+        {
+            direction = -1;
+        }
+        if input::is_key_pressed(ctx, Key::Right)
+            || input::is_gamepad_button_pressed(
+                ctx,
+                0,
+                GamepadButton::Right,
+            )
+        {
+            direction = 1;
+        }
+        if direction != 0
+            && self.screen == Screen::Local
+            && self.selection == 0
+        {
+            self.local_players = (self.local_players as i32 + direction)
+                .clamp(MIN_PLAYERS as i32, MAX_PLAYERS as i32)
+                as usize;
+            play_sound(resources, settings, "menuselect");
+        }
+        if direction != 0 && self.is_skin_selected() {
+            let names = resources.palettes.names();
+            // an empty skin is the default, before the first in the list
+            let index = names
+                .iter()
+                .position(|name| *name == settings.skin)
+                .map_or(0, |index| index + 1);
+            let next_index = (index as i32 + direction)
+                .rem_euclid(names.len() as i32 + 1)
+                as usize;
+            settings.skin = match next_index {
+                0 => String::new(),
+                _ => names[next_index - 1].clone(),
+            };
+            play_sound(resources, settings, "menuselect");
+            return MenuAction::SettingsChanged;
+        }
+        if self.screen == Screen::Settings
+            && direction != 0
+            && adjust_setting(settings, self.selection, direction)
+        {
+            play_sound(resources, settings, "menuselect");
+            return MenuAction::SettingsChanged;
+        }
+
+        let is_back_pressed = input::is_key_pressed(ctx, Key::Escape)
+            || input::is_gamepad_button_pressed(ctx, 0, GamepadButton::B);
+        if is_back_pressed {
+            if self.screen == Screen::Main {
+                return MenuAction::Quit;
+            }
+            self.go_to(Screen::Main);
+            play_sound(resources, settings, "menuselect");
+            return MenuAction::None;
+        }
+
+        // alt+enter toggles fullscreen instead
+        let is_confirm_pressed = input::is_key_pressed(ctx, Key::Enter)
+            && !input::is_key_modifier_down(ctx, KeyModifier::Alt)
+            || input::is_key_pressed(ctx, Key::Z)
+            || input::is_gamepad_button_pressed(ctx, 0, GamepadButton::A);
+        if !is_confirm_pressed {
+            return MenuAction::None;
+        }
+        let action = self.confirm(settings);
+        if let MenuAction::None = action {
+            play_sound(resources, settings, "menuselect");
+        } else {
+            play_sound(resources, settings, "menustart");
+        }
+        return action;
+    }
+
+    fn confirm(&mut self, settings: &Settings) -> MenuAction {
+        match (self.screen, self.selection) {
+            (Screen::Main, 0) => self.go_to(Screen::Host),
+            (Screen::Main, 1) => self.go_to(Screen::Join),
+            (Screen::Main, 2) => self.go_to(Screen::Local),
+            (Screen::Main, 3) => return MenuAction::Training,
+            (Screen::Main, 4) => self.go_to(Screen::Settings),
+            (Screen::Main, 5) => return MenuAction::Quit,
+            (Screen::Host, 2) => match self.host_port.parse() {
+                Ok(local_port) => {
+                    return MenuAction::Host {
+                        local_port,
+                        input_delay: settings.input_delay,
+                    }
+                }
+                Err(_) => self.set_message("invalid port"),
+            },
+            (Screen::Local, 2) => {
+                return MenuAction::Local {
+                    num_players: self.local_players,
+                }
+            }
+            (Screen::Join, 3) => {
+                let host_addr = self.join_address.parse();
+                let local_port = self.join_port.parse();
+                match (host_addr, local_port) {
+                    (Ok(host_addr), Ok(local_port)) => {
+                        return MenuAction::Join {
+                            host_addr,
+                            local_port,
+                            input_delay: settings.input_delay,
+                        }
+                    }
+                    (Err(_), _) => {
+                        self.set_message("enter the host as ip:port")
+                    }
+                    (_, Err(_)) => self.set_message("invalid port"),
+                }
+            }
+            (_, selection)
+                if selection == self.items(settings).len() - 1 =>
+            {
+                self.go_to(Screen::Main)
+            }
+            _ => (),
+        }
+        return MenuAction::None;
+    }
+
+    fn go_to(&mut self, screen: Screen) {
+        self.screen = screen;
+        self.selection = 0;
+        self.message.clear();
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        text: &mut Text,
+        settings: &Settings,
+    ) {
+        let items = self.items(settings);
+        let mut lines: Vec<(&str, Color)> =
+            vec![(self.title(), Color::WHITE), ("", Color::WHITE)];
+        for (item_num, item) in items.iter().enumerate() {
+            let color = if item_num == self.selection {
+                SELECTED_COLOR
+            } else {
+                Color::WHITE
+            };
+            lines.push((item, color));
+        }
+        lines.push(("", Color::WHITE));
+        lines.push((&self.message, Color::WHITE));
+        draw_lines(ctx, text, &lines);
+    }
+}
+
+// changes the setting at this row of the settings screen, returning
+// whether anything changed
+fn adjust_setting(
+    settings: &mut Settings,
+    selection: usize,
+    direction: i32,
+) -> bool {
+    match selection {
+        0 => {
+            settings.input_delay = match (settings.input_delay, direction)
+            {
+                (Some(0), -1) => None,
+                (Some(MAX_INPUT_DELAY), 1) => None,
+                (Some(delay), -1) => Some(delay - 1),
+                (Some(delay), _) => Some(delay + 1),
+                (None, -1) => Some(MAX_INPUT_DELAY),
+                (None, _) => Some(0),
+            }
+        }
+        1 => {
+            let names = Ruleset::available();
+            if names.is_empty() {
+                return false;
+            }
+            let index = names
+                .iter()
+                .position(|name| *name == settings.ruleset)
+                .unwrap_or(0);
+            let next_index = (index as i32 + direction)
+                .rem_euclid(names.len() as i32)
+                as usize;
+            settings.ruleset = names[next_index].clone();
+        }
+        2 => settings.fullscreen = !settings.fullscreen,
+        3 => {
+            settings.window_scale = (settings.window_scale + direction)
+                .clamp(1, MAX_WINDOW_SCALE)
+        }
+        4 => settings.integer_scaling = !settings.integer_scaling,
+        5 => settings.vsync = !settings.vsync,
+        6 => {
+            settings.master_volume = (settings.master_volume
+                + direction * VOLUME_STEP)
+                .clamp(0, MAX_VOLUME)
+        }
+        7 => {
+            settings.sfx_volume = (settings.sfx_volume
+                + direction * VOLUME_STEP)
+                .clamp(0, MAX_VOLUME)
+        }
+        8 => {
+            settings.music_volume = (settings.music_volume
+                + direction * VOLUME_STEP)
+                .clamp(0, MAX_VOLUME)
+        }
+        9 => settings.screen_effects = !settings.screen_effects,
+        _ => return false,
+    }
+    return true;
+}
+
+fn skin_item(settings: &Settings) -> String {
+    if settings.skin.is_empty() {
+        return "SKIN: < AUTO >".to_string();
+    }
+    return format!("SKIN: < {} >", settings.skin.to_uppercase());
+}
+
+fn on_off(value: bool) -> &'static str {
+    return if value { "ON" } else { "OFF" };
+}
+
+fn play_sound(resources: &Resources, settings: &Settings, name: &str) {
+    let sound = &resources.sounds[name];
+    sound.stop();
+    sound.set_volume(settings.sfx_volume());
+    sound.play();
+}
+
+// draws each line horizontally centered on the 320x180 canvas
+pub fn draw_lines(
+    ctx: &mut Context,
+    text: &mut Text,
+    lines: &[(&str, Color)],
+) {
+    let top = 90.0 - lines.len() as f32 * LINE_HEIGHT / 2.0;
+    for (line_num, (line, color)) in lines.iter().enumerate() {
+        text.set_content(*line);
+        let width = match text.get_bounds(ctx) {
+            Some(bounds) => bounds.width,
+            None => 0.0,
+        };
+        text.draw(
+            ctx,
+            tetra::graphics::DrawParams::new()
+                .position(Vec2::new(
+                    (160.0 - width / 2.0).round(),
+                    top + line_num as f32 * LINE_HEIGHT,
+                ))
+                .color(*color),
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use quick_xml::de::from_str;
+use serde::Deserialize;
+use std::fs;
+use tetra::graphics::{Color, ImageData};
+
+const PALETTES_PATH: &str = "./resources/palettes.xml";
+
+type Rgb = (u8, u8, u8);
+
+#[derive(Deserialize)]
+struct PalettesData {
+    #[serde(rename = "skin", default)]
+    skins: Vec<SkinData>,
+}
+
+#[derive(Deserialize)]
+struct SkinData {
+    name: String,
+    player: String,
+    boomerang: String,
+}
+
+pub struct Skin {
+    pub name: String,
+    player: Vec<Rgb>,
+    boomerang: Vec<Rgb>,
+}
+
+// recolorings of the first player's graphics. the first skin has the
+// colors they're drawn in, and the rest swap those out
+pub struct Palettes {
+    pub skins: Vec<Skin>,
+}
+
+impl Palettes {
+    pub fn load() -> Result<Self, String> {
+        let xml = fs::read_to_string(PALETTES_PATH).map_err(|err| {
+            format!("could not read {}: {}", PALETTES_PATH, err)
+        })?;
+        let data: PalettesData = from_str(&xml).map_err(|err| {
+            format!("could not parse {}: {}", PALETTES_PATH, err)
+        })?;
+        let mut skins: Vec<Skin> = Vec::new();
+        for skin in data.skins {
+            let parsed = Skin {
+                player: parse_colors(&skin.player).ok_or(format!(
+                    "bad player colors in {}",
+                    skin.name
+                ))?,
+                boomerang: parse_colors(&skin.boomerang).ok_or(
+                    format!("bad boomerang colors in {}", skin.name),
+                )?,
+                name: skin.name,
+            };
+            if let Some(first) = skins.first() {
+                if parsed.player.len() != first.player.len()
+                    || parsed.boomerang.len() != first.boomerang.len()
+                {
+                    return Err(format!(
+                        "{} has a different number of colors than {}",
+                        parsed.name, first.name
+                    ));
+                }
+            }
+            skins.push(parsed);
+        }
+        if skins.is_empty() {
+            return Err(format!("no skins in {}", PALETTES_PATH));
+        }
+        return Ok(Palettes { skins });
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        return self.skins.iter().map(|skin| skin.name.clone()).collect();
+    }
+
+    pub fn default_skin(&self, player_num: usize) -> &str {
+        return &self.skins[player_num % self.skins.len()].name;
+    }
+
+    pub fn recolor_player(
+        &self,
+        image: &ImageData,
+        skin: &Skin,
+    ) -> ImageData {
+        return recolor(image, &self.skins[0].player, &skin.player);
+    }
+
+    pub fn recolor_boomerang(
+        &self,
+        image: &ImageData,
+        skin: &Skin,
+    ) -> ImageData {
+        return recolor(image, &self.skins[0].boomerang, &skin.boomerang);
+    }
+
+    // gives every player the skin they picked, unless someone earlier
+    // already has one that looks the same. then they get their default
+    // skin, or failing that the first one nobody has. every peer gets
+    // the same picks in the same order, so they all agree
+    pub fn resolve(&self, picks: &[String]) -> Vec<String> {
+        let mut taken: Vec<&Skin> = Vec::new();
+        for (player_num, pick) in picks.iter().enumerate() {
+            let candidates =
+                [pick.as_str(), self.default_skin(player_num)];
+            let skin = candidates
+                .iter()
+                .filter_map(|name| self.find(name))
+                .chain(self.skins.iter())
+                .find(|skin| {
+                    !taken.iter().any(|other| other.player == skin.player)
+                })
+                // more players than skins, so someone has to share
+                .unwrap_or(&self.skins[player_num % self.skins.len()]);
+            taken.push(skin);
+        }
+        return taken.iter().map(|skin| skin.name.clone()).collect();
+    }
+
+    fn find(&self, name: &str) -> Option<&Skin> {
+        return self.skins.iter().find(|skin| skin.name == name);
+    }
+}
+
+// colors written as hex, separated by spaces
+fn parse_colors(text: &str) -> Option<Vec<Rgb>> {
+    return text
+        .split_whitespace()
+        .map(|hex| Color::try_hex(hex).ok().map(to_rgb))
+        .collect();
+}
+
+fn to_rgb(color: Color) -> Rgb {
+    return (
+        (color.r * 255.0).round() as u8,
+        (color.g * 255.0).round() as u8,
+        (color.b * 255.0).round() as u8,
+    );
+}
+
+fn recolor(image: &ImageData, from: &[Rgb], to: &[Rgb]) -> ImageData {
+    let mut image = image.clone();
+    image.transform(|_, color| {
+        return match from.iter().position(|rgb| *rgb == to_rgb(color)) {
+            Some(index) => {
+                let (r, g, b) = to[index];
+                Color::rgba8(r, g, b, (color.a * 255.0).round() as u8)
+            }
+            None => color,
+        };
+    });
+    return image;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(names: &[&str]) -> Vec<String> {
+        return names.iter().map(|name| name.to_string()).collect();
+    }
+
+    fn skin(name: &str, player: Rgb) -> Skin {
+        return Skin {
+            name: name.to_string(),
+            player: vec![player],
+            boomerang: vec![(0, 0, 0)],
+        };
+    }
+
+    #[test]
+    fn different_picks_are_kept() {
+        let palettes = Palettes::load().unwrap();
+        assert_eq!(
+            palettes.resolve(&picks(&["green", "red", "gold"])),
+            picks(&["green", "red", "gold"])
+        );
+    }
+
+    #[test]
+    fn empty_or_unknown_picks_get_the_default() {
+        let palettes = Palettes::load().unwrap();
+        assert_eq!(
+            palettes.resolve(&picks(&["", "nope"])),
+            picks(&[palettes.default_skin(0), palettes.default_skin(1)])
+        );
+    }
+
+    #[test]
+    fn a_later_clash_falls_back_to_the_default_then_the_first_free() {
+        let palettes = Palettes::load().unwrap();
+        let default = palettes.default_skin(1).to_string();
+        let first = palettes.skins[0].name.clone();
+        let third_default = palettes.default_skin(2).to_string();
+        assert_eq!(
+            palettes.resolve(&picks(&["red", "red"])),
+            picks(&["red", &default])
+        );
+        // the default is taken as well
+        assert_eq!(
+            palettes.resolve(&picks(&[&default, &default])),
+            picks(&[&default, &first])
+        );
+        assert_eq!(
+            palettes.resolve(&picks(&[&first, &default, &default])),
+            picks(&[&first, &default, &third_default])
+        );
+    }
+
+    #[test]
+    fn skins_with_the_same_colors_count_as_taken() {
+        let palettes = Palettes {
+            skins: vec![
+                skin("gold", (1, 1, 1)),
+                skin("also_gold", (1, 1, 1)),
+                skin("blue", (2, 2, 2)),
+            ],
+        };
+        assert_eq!(
+            palettes.resolve(&picks(&["gold", "also_gold"])),
+            picks(&["gold", "blue"])
+        );
+    }
+
+    #[test]
+    fn more_players_than_skins_share() {
+        let palettes = Palettes {
+            skins: vec![skin("gold", (1, 1, 1)), skin("blue", (2, 2, 2))],
+        };
+        assert_eq!(
+            palettes.resolve(&picks(&["", "", ""])),
+            picks(&["gold", "blue", "gold"])
+        );
+    }
+}
@@ -1,58 +1,298 @@
-use crate::utils::IntVector2D;
-use serde::{Deserialize, Serialize};
+use crate::utils::{aim_heading, IntVector2D, AIM_DIRECTIONS};
 
-#[derive(Clone, Serialize, Deserialize)]
+pub const GROUND_DUST_ANIMATION_SPEED: usize = 4;
+pub const GROUND_DUST_ANIMATION_FRAMES: usize = 5;
+pub const SIMPLE_ANIMATION_SPEED: usize = 10;
+pub const SIMPLE_ANIMATION_FRAMES: usize = 5;
+
+pub const MAX_PARTICLES: usize = 100;
+
+// a burst of particles. velocities are in thousandths of a pixel per
+// frame, and the burst is spread evenly across `spread` aim directions
+// starting from `direction`
+pub struct Emitter {
+    pub name: &'static str,
+    pub animation: &'static str,
+    pub count: i32,
+    pub direction: u8,
+    pub spread: u8,
+    pub speed: i32,
+    pub gravity: i32,
+    pub lifetime: i32,
+}
+
+pub const EMITTERS: [Emitter; 6] = [
+    Emitter {
+        name: "grounddust",
+        animation: "grounddust",
+        count: 1,
+        direction: 0,
+        spread: 0,
+        speed: 0,
+        gravity: 0,
+        lifetime: (GROUND_DUST_ANIMATION_SPEED
+            * GROUND_DUST_ANIMATION_FRAMES) as i32,
+    },
+    Emitter {
+        name: "explosion",
+        animation: "simple",
+        count: 24,
+        direction: 0,
+        spread: AIM_DIRECTIONS,
+        speed: 9000,
+        gravity: 0,
+        lifetime: (SIMPLE_ANIMATION_SPEED * SIMPLE_ANIMATION_FRAMES)
+            as i32,
+    },
+    // four diagonal sparks from a clash or parry
+    Emitter {
+        name: "sparks",
+        animation: "simple",
+        count: 4,
+        direction: AIM_DIRECTIONS / 8,
+        spread: AIM_DIRECTIONS,
+        speed: 4000,
+        gravity: 0,
+        lifetime: (SIMPLE_ANIMATION_SPEED * SIMPLE_ANIMATION_FRAMES)
+            as i32,
+    },
+    // a ring from the feet on a super jump
+    Emitter {
+        name: "superjump",
+        animation: "simple",
+        count: 8,
+        direction: 0,
+        spread: AIM_DIRECTIONS,
+        speed: 2500,
+        gravity: 0,
+        lifetime: (SIMPLE_ANIMATION_SPEED * SIMPLE_ANIMATION_FRAMES)
+            as i32,
+    },
+    // thrown up and off the wall while wall sliding, falling back down.
+    // sparks off a wall on the player's left fly up and to the right
+    Emitter {
+        name: "leftwallsparks",
+        animation: "simple",
+        count: 2,
+        direction: AIM_DIRECTIONS / 4 * 3,
+        spread: AIM_DIRECTIONS / 4,
+        speed: 1500,
+        gravity: 150,
+        lifetime: SIMPLE_ANIMATION_SPEED as i32 * 2,
+    },
+    Emitter {
+        name: "rightwallsparks",
+        animation: "simple",
+        count: 2,
+        direction: AIM_DIRECTIONS / 8 * 5,
+        spread: AIM_DIRECTIONS / 4,
+        speed: 1500,
+        gravity: 150,
+        lifetime: SIMPLE_ANIMATION_SPEED as i32 * 2,
+    },
+];
+
+pub fn get_emitter(name: &str) -> Option<&'static Emitter> {
+    return EMITTERS.iter().find(|emitter| emitter.name == name);
+}
+
+#[derive(Clone)]
 pub struct Particle {
+    // the effect that spawned it, so a rolled back effect can be removed
+    pub effect_id: u32,
     pub position: IntVector2D,
     pub velocity: IntVector2D,
+    pub gravity: i32,
+    // frames left before the particle is freed
+    pub lifetime: i32,
     pub current_animation: String,
     pub current_animation_frame: usize,
 }
 
-pub const GROUND_DUST_ANIMATION_SPEED: usize = 4;
-pub const GROUND_DUST_ANIMATION_FRAMES: usize = 5;
-pub const SIMPLE_ANIMATION_SPEED: usize = 10;
-pub const SIMPLE_ANIMATION_FRAMES: usize = 5;
-
 impl Particle {
     pub fn new() -> Particle {
         return Particle {
+            effect_id: 0,
             position: IntVector2D { x: 0, y: 0 },
             velocity: IntVector2D { x: 0, y: 0 },
+            gravity: 0,
+            lifetime: 0,
             current_animation: "none".to_string(),
             current_animation_frame: 0,
         };
     }
 
+    pub fn is_alive(&self) -> bool {
+        return self.lifetime > 0;
+    }
+
     pub fn advance(&mut self) {
         self.current_animation_frame += 1;
-        // TODO: This is a big flaw in how data is organized...
-        //
-        if self.current_animation == "grounddust" {
-            if self.current_animation_frame
-                >= GROUND_DUST_ANIMATION_SPEED
-                    * GROUND_DUST_ANIMATION_FRAMES
-            {
-                self.set_animation("none");
+        self.lifetime -= 1;
+        self.velocity.y += self.gravity;
+        self.position.x += self.velocity.x;
+        self.position.y += self.velocity.y;
+    }
+}
+
+// a fixed pool of particles. free slots are kept on a stack so spawning
+// never scans the pool, and when every slot is taken a new particle
+// replaces whichever live one has the least time left
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    free: Vec<usize>,
+}
+
+impl ParticleSystem {
+    pub fn new() -> ParticleSystem {
+        return ParticleSystem {
+            particles: vec![Particle::new(); MAX_PARTICLES],
+            free: (0..MAX_PARTICLES).rev().collect(),
+        };
+    }
+
+    pub fn emit(
+        &mut self,
+        emitter: &Emitter,
+        position: IntVector2D,
+        effect_id: u32,
+    ) {
+        for particle_num in 0..emitter.count {
+            let spread_step =
+                emitter.spread as i32 * particle_num / emitter.count;
+            let direction = (emitter.direction as i32 + spread_step)
+                .rem_euclid(AIM_DIRECTIONS as i32)
+                as u8;
+            let index = self.allocate();
+            self.particles[index] = Particle {
+                effect_id,
+                position,
+                velocity: aim_heading(direction, emitter.speed),
+                gravity: emitter.gravity,
+                lifetime: emitter.lifetime,
+                current_animation: emitter.animation.to_string(),
+                current_animation_frame: 0,
+            };
+        }
+    }
+
+    pub fn advance(&mut self) {
+        for index in 0..self.particles.len() {
+            if !self.particles[index].is_alive() {
+                continue;
+            }
+            self.particles[index].advance();
+            if !self.particles[index].is_alive() {
+                self.free_particle(index);
             }
-        } else if self.current_animation == "simple" {
-            if self.current_animation_frame
-                >= SIMPLE_ANIMATION_SPEED * SIMPLE_ANIMATION_FRAMES
+        }
+    }
+
+    pub fn cancel(&mut self, effect_id: u32) {
+        for index in 0..self.particles.len() {
+            if self.particles[index].is_alive()
+                && self.particles[index].effect_id == effect_id
             {
-                self.set_animation("none");
+                self.free_particle(index);
             }
         }
+    }
 
-        self.position.x += self.velocity.x;
-        self.position.y += self.velocity.y;
+    fn free_particle(&mut self, index: usize) {
+        self.particles[index].lifetime = 0;
+        self.particles[index].current_animation = "none".to_string();
+        self.free.push(index);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Particle> {
+        return self
+            .particles
+            .iter()
+            .filter(|particle| particle.is_alive());
     }
 
-    pub fn set_animation(&mut self, new_animation: &str) {
-        let old_animation = self.current_animation.clone();
-        self.current_animation = new_animation.to_string();
-        if old_animation != self.current_animation {
-            self.current_animation_frame = 0;
-            self.velocity.zero();
+    fn allocate(&mut self) -> usize {
+        if let Some(index) = self.free.pop() {
+            return index;
+        }
+        let mut oldest = 0;
+        for index in 1..self.particles.len() {
+            if self.particles[index].lifetime
+                < self.particles[oldest].lifetime
+            {
+                oldest = index;
+            }
         }
+        return oldest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitter(count: i32, lifetime: i32) -> Emitter {
+        return Emitter {
+            name: "test",
+            animation: "simple",
+            count,
+            direction: 0,
+            spread: AIM_DIRECTIONS,
+            speed: 1000,
+            gravity: 0,
+            lifetime,
+        };
+    }
+
+    const ORIGIN: IntVector2D = IntVector2D { x: 0, y: 0 };
+
+    #[test]
+    fn particles_are_freed_when_their_lifetime_runs_out() {
+        let mut particles = ParticleSystem::new();
+        particles.emit(&emitter(3, 2), ORIGIN, 1);
+        assert_eq!(particles.iter().count(), 3);
+        particles.advance();
+        assert_eq!(particles.iter().count(), 3);
+        particles.advance();
+        assert_eq!(particles.iter().count(), 0);
+        // and every slot can be used again
+        particles.emit(&emitter(MAX_PARTICLES as i32, 2), ORIGIN, 2);
+        assert_eq!(particles.iter().count(), MAX_PARTICLES);
+    }
+
+    #[test]
+    fn a_full_pool_replaces_the_particle_with_the_least_time_left() {
+        let mut particles = ParticleSystem::new();
+        particles.emit(&emitter(1, 5), ORIGIN, 1);
+        particles.emit(&emitter(MAX_PARTICLES as i32 - 1, 50), ORIGIN, 2);
+        particles.emit(&emitter(1, 50), ORIGIN, 3);
+        assert_eq!(particles.iter().count(), MAX_PARTICLES);
+        assert!(particles.iter().all(|particle| particle.effect_id != 1));
+        assert!(particles.iter().any(|particle| particle.effect_id == 3));
+    }
+
+    #[test]
+    fn cancelling_an_effect_frees_only_its_particles() {
+        let mut particles = ParticleSystem::new();
+        particles.emit(&emitter(4, 10), ORIGIN, 1);
+        particles.emit(&emitter(2, 10), ORIGIN, 2);
+        particles.cancel(1);
+        assert_eq!(particles.iter().count(), 2);
+        assert!(particles.iter().all(|particle| particle.effect_id == 2));
+    }
+
+    #[test]
+    fn a_burst_is_spread_evenly() {
+        let mut particles = ParticleSystem::new();
+        particles.emit(&emitter(4, 10), ORIGIN, 1);
+        let mut velocities: Vec<(i32, i32)> = particles
+            .iter()
+            .map(|particle| (particle.velocity.x, particle.velocity.y))
+            .collect();
+        velocities.sort();
+        assert_eq!(
+            velocities,
+            vec![(-1000, 0), (0, -1000), (0, 1000), (1000, 0)]
+        );
     }
 }
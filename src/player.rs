@@ -3,49 +3,28 @@ use fixed_macro::fixed;
 use serde::{Deserialize, Serialize};
 
 use crate::game::{
-    INPUT_DODGE, INPUT_DOWN, INPUT_JUMP, INPUT_LEFT, INPUT_RIGHT, INPUT_UP,
+    INPUT_DODGE, INPUT_DOWN, INPUT_JUMP, INPUT_LEFT, INPUT_RIGHT,
+    INPUT_UP, NO_AIM,
 };
-use crate::level::{Level, TILE_SIZE};
+use crate::hurtbox::Hurtboxes;
+use crate::level::Level;
+use crate::ruleset::Ruleset;
 use crate::utils::{
-    approach, clamp, do_hitboxes_overlap, input_check, input_pressed,
-    input_released, Hitbox, IntVector2D,
+    aim_heading, approach, clamp, do_hitboxes_overlap, input_check,
+    input_pressed, input_released, Hitbox, IntVector2D,
 };
 
 // The frame rate of the original esports heaven
 pub const OG_FPS: i32 = 60;
-
-pub const RUN_ACCEL: i32 = 400 * 1000;
-pub const RUN_ACCEL_TURN_MULTIPLIER: i32 = 2;
-pub const RUN_DECEL: i32 = RUN_ACCEL * RUN_ACCEL_TURN_MULTIPLIER;
-pub const AIR_ACCEL: i32 = 360 * 1000;
-pub const AIR_DECEL: i32 = 360 * 1000;
-pub const MAX_RUN_SPEED: i32 = 100 * 1000;
-pub const MAX_SUPERJUMP_SPEED_X: i32 = 250 * 1000;
-pub const MAX_SUPERJUMP_SPEED_X_OFF_WALL_SLIDE: i32 = 150 * 1000;
-pub const MAX_AIR_SPEED: i32 = 120 * 1000;
-pub const GRAVITY: i32 = 500 * 1000;
-pub const FASTFALL_GRAVITY: i32 = 1200 * 1000;
-pub const GRAVITY_ON_WALL: i32 = 150 * 1000;
-pub const JUMP_POWER: i32 = 160 * 1000;
-pub const JUMP_CANCEL_POWER: i32 = 40 * 1000;
-pub const WALL_JUMP_POWER_X: i32 = 130 * 1000;
-pub const WALL_JUMP_POWER_Y: i32 = 120 * 1000;
-pub const SUPER_WALL_JUMP_POWER_X: i32 = 74286;
-pub const SUPER_WALL_JUMP_POWER_Y: i32 = 210000;
-pub const WALL_STICKINESS: i32 = 60 * 1000;
-pub const MAX_FALL_SPEED: i32 = 270 * 1000;
-pub const MAX_FALL_SPEED_ON_WALL: i32 = 200 * 1000;
-pub const MAX_FASTFALL_SPEED: i32 = 500 * 1000;
-pub const DOUBLE_JUMP_POWER_Y: i32 = 130 * 1000;
-pub const DODGE_DURATION: i32 = 9;
-pub const SLIDE_DURATION: i32 = 19;
-pub const SLIDE_DECEL: i32 = 100 * 1000;
-pub const DODGE_COOLDOWN: i32 = 9;
-pub const DODGE_SPEED: i32 = 260 * 1000;
+pub const WIDTH: i32 = 6000;
+pub const HEIGHT: i32 = 12000;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
+    // collides with the level; being hit uses the hurtbox instead
     pub hitbox: Hitbox,
+    // the current animation's hurtbox, relative to the hitbox
+    pub hurtbox_offset: Hitbox,
     pub velocity: IntVector2D,
     pub current_animation: String,
     pub current_animation_frame: usize,
@@ -61,10 +40,21 @@ pub struct Player {
     pub is_wall_sliding: bool,
     pub is_super_jumping: bool,
     pub is_super_jumping_off_wall_slide: bool,
-    pub collided_with_boomerang: bool,
-    pub collided_with_player: bool,
+    // frames left on a buffered jump press
+    pub jump_buffer_timer: i32,
+    // frames left to jump as if still on the ground or a wall
+    pub ground_coyote_timer: i32,
+    pub wall_coyote_timer: i32,
+    pub was_on_left_wall: bool,
+    // the player nums whose boomerang or body touched us this frame
+    pub collided_with_boomerangs: Vec<usize>,
+    pub collided_with_players: Vec<usize>,
     pub will_die: bool,
     pub is_dead: bool,
+    pub stocks_lost: i32,
+    // frames until a dead player with stocks left respawns
+    pub respawn_timer: i32,
+    pub invulnerable_timer: i32,
     pub particle_spawns: Vec<(IntVector2D, String)>,
     pub sound_commands: Vec<(String, String, i32)>,
     pub start: IntVector2D,
@@ -76,6 +66,12 @@ impl Player {
             hitbox: Hitbox {
                 x,
                 y,
+                width: WIDTH,
+                height: HEIGHT,
+            },
+            hurtbox_offset: Hitbox {
+                x: 0,
+                y: 0,
                 width: 6000,
                 height: 12000,
             },
@@ -88,19 +84,26 @@ impl Player {
             can_double_jump: true,
             can_dodge: true,
             dodge_timer: 0,
-            dodge_timer_duration: DODGE_COOLDOWN,
+            dodge_timer_duration: 0,
             dodge_cooldown: 0,
             is_sliding: false,
             is_wall_sliding: false,
             is_super_jumping: false,
             is_super_jumping_off_wall_slide: false,
-            collided_with_boomerang: false,
-            collided_with_player: false,
+            jump_buffer_timer: 0,
+            ground_coyote_timer: 0,
+            wall_coyote_timer: 0,
+            was_on_left_wall: false,
+            collided_with_boomerangs: Vec::new(),
+            collided_with_players: Vec::new(),
             will_die: false,
             is_dead: false,
+            stocks_lost: 0,
+            respawn_timer: 0,
+            invulnerable_timer: 0,
             particle_spawns: Vec::new(),
             sound_commands: Vec::new(),
-            start: IntVector2D { x: x, y: y },
+            start: IntVector2D { x, y },
         };
     }
 
@@ -108,9 +111,12 @@ impl Player {
         &mut self,
         input: u8,
         prev_input: u8,
+        aim: u8,
         level: &Level,
-        other_player_hitbox: &Hitbox,
-        other_boomerang_hitbox: &Hitbox,
+        ruleset: &Ruleset,
+        hurtboxes: &Hurtboxes,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        opponent_boomerang_hitboxes: &[(usize, Hitbox)],
     ) {
         let mut is_on_ground =
             self.collide(level, self.hitbox.x, self.hitbox.y + 1);
@@ -120,32 +126,35 @@ impl Player {
             self.collide(level, self.hitbox.x + 1, self.hitbox.y);
         let mut is_on_wall = is_on_left_wall || is_on_right_wall;
 
-        self.collided_with_player = false;
-        self.collided_with_boomerang = false;
+        self.collided_with_players.clear();
+        self.collided_with_boomerangs.clear();
 
         if self.dodge_timer > 0 {
             self.dodge_movement(
                 input,
                 prev_input,
                 level,
+                ruleset,
                 is_on_ground,
                 is_on_left_wall,
                 is_on_right_wall,
                 is_on_wall,
-                other_player_hitbox,
-                other_boomerang_hitbox,
+                opponent_hurtboxes,
+                opponent_boomerang_hitboxes,
             );
         } else {
             self.movement(
                 input,
                 prev_input,
+                aim,
                 level,
+                ruleset,
                 is_on_ground,
                 is_on_left_wall,
                 is_on_right_wall,
                 is_on_wall,
-                other_player_hitbox,
-                other_boomerang_hitbox,
+                opponent_hurtboxes,
+                opponent_boomerang_hitboxes,
             );
         }
 
@@ -160,7 +169,7 @@ impl Player {
         if self.is_wall_sliding && !is_on_wall {
             self.is_wall_sliding = false;
             if self.was_on_wall && self.velocity.y <= 0 {
-                self.velocity.y = -JUMP_CANCEL_POWER * 2;
+                self.velocity.y = -ruleset.jump_cancel_power * 2;
             }
         }
 
@@ -184,10 +193,13 @@ impl Player {
             } else if self.velocity.x > 0 && input_check(INPUT_LEFT, input)
                 || self.velocity.x < 0 && input_check(INPUT_RIGHT, input)
             {
-                if self.current_animation != "skid".to_string() {
+                if self.current_animation != "skid" {
                     self.add_sound_command("skid", "play", 100);
                 }
                 self.set_animation("skid");
+                if self.current_animation_frame.is_multiple_of(6) {
+                    self.make_dust_at_feet();
+                }
             } else {
                 self.set_animation("run");
             }
@@ -195,9 +207,19 @@ impl Player {
         } else {
             self.set_animation("idle");
         }
+        self.hurtbox_offset = match hurtboxes.get(&self.current_animation)
+        {
+            Some(offset) => offset,
+            None => Hitbox {
+                x: 0,
+                y: 0,
+                width: self.hitbox.width,
+                height: self.hitbox.height,
+            },
+        };
 
         // handle sound
-        if self.current_animation == "run".to_string() {
+        if self.current_animation == "run" {
             self.add_sound_command("run", "loop", 100);
         } else {
             self.add_sound_command("run", "stop", 100);
@@ -207,8 +229,8 @@ impl Player {
             if self.is_sliding {
                 self.add_sound_command("wallslide", "loop", 100);
             } else {
-                let slide_volume =
-                    self.velocity.y.abs() * 100 / MAX_FALL_SPEED_ON_WALL;
+                let slide_volume = self.velocity.y.abs() * 100
+                    / ruleset.max_fall_speed_on_wall;
                 self.add_sound_command("wallslide", "loop", slide_volume);
             }
         } else {
@@ -219,6 +241,7 @@ impl Player {
         let prev_dodge_timer = self.dodge_timer;
         self.dodge_timer = approach(self.dodge_timer, 0, 1);
         self.dodge_cooldown = approach(self.dodge_cooldown, 0, 1);
+        self.invulnerable_timer = approach(self.invulnerable_timer, 0, 1);
 
         if self.dodge_timer == 0 && prev_dodge_timer > 0 {
             if self.is_sliding {
@@ -226,11 +249,11 @@ impl Player {
             } else if self.is_wall_sliding {
                 self.is_wall_sliding = false;
             } else if self.velocity.y < 0 {
-                self.velocity.y = -JUMP_CANCEL_POWER;
+                self.velocity.y = -ruleset.jump_cancel_power;
             } else if self.velocity.y > 0 {
-                self.velocity.y = MAX_FALL_SPEED / 2;
+                self.velocity.y = ruleset.max_fall_speed / 2;
             }
-            self.dodge_cooldown = DODGE_COOLDOWN;
+            self.dodge_cooldown = ruleset.dodge_cooldown;
         }
 
         if !self.was_on_ground && is_on_ground {
@@ -250,25 +273,26 @@ impl Player {
         input: u8,
         prev_input: u8,
         level: &Level,
+        ruleset: &Ruleset,
         is_on_ground: bool,
         is_on_left_wall: bool,
         is_on_right_wall: bool,
         _is_on_wall: bool,
-        other_player_hitbox: &Hitbox,
-        other_boomerang_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        opponent_boomerang_hitboxes: &[(usize, Hitbox)],
     ) {
         if self.is_sliding {
-            let mut gravity = GRAVITY;
+            let mut gravity = ruleset.gravity;
             if input_check(INPUT_DOWN, input)
-                && self.velocity.y > -JUMP_CANCEL_POWER
+                && self.velocity.y > -ruleset.jump_cancel_power
             {
-                gravity = FASTFALL_GRAVITY;
+                gravity = ruleset.fastfall_gravity;
             }
             self.velocity.y += gravity / OG_FPS;
             self.velocity.y =
-                std::cmp::min(self.velocity.y, MAX_FASTFALL_SPEED);
+                std::cmp::min(self.velocity.y, ruleset.max_fastfall_speed);
             self.velocity.x =
-                approach(self.velocity.x, 0, SLIDE_DECEL / OG_FPS);
+                approach(self.velocity.x, 0, ruleset.slide_decel / OG_FPS);
 
             if input_pressed(INPUT_JUMP, input, prev_input) {
                 // ugly fixed point math
@@ -280,7 +304,7 @@ impl Player {
                         numerator.saturating_div(denominator),
                     ),
                 );
-                let new_velocity_y = I32F32::from_num(-JUMP_POWER)
+                let new_velocity_y = I32F32::from_num(-ruleset.jump_power)
                     .saturating_div(jump_modifier);
                 self.velocity.y =
                     new_velocity_y.saturating_to_num::<i32>();
@@ -292,46 +316,52 @@ impl Player {
                 self.is_sliding = false;
                 if numerator.saturating_div(denominator) > 0.5 {
                     self.is_super_jumping = true;
+                    self.make_burst_at_feet();
                 }
                 self.add_sound_command("superjump", "play", 100);
             }
         } else if self.is_wall_sliding {
-            let mut gravity = GRAVITY;
+            let mut gravity = ruleset.gravity;
             if input_check(INPUT_DOWN, input)
-                && self.velocity.y > -JUMP_CANCEL_POWER
+                && self.velocity.y > -ruleset.jump_cancel_power
             {
-                gravity = FASTFALL_GRAVITY;
+                gravity = ruleset.fastfall_gravity;
             }
             self.velocity.y += gravity / OG_FPS;
             self.velocity.y =
-                std::cmp::min(self.velocity.y, MAX_FASTFALL_SPEED);
+                std::cmp::min(self.velocity.y, ruleset.max_fastfall_speed);
+            if self.current_animation_frame.is_multiple_of(3) {
+                self.make_sparks_on_wall(is_on_left_wall);
+            }
             if input_pressed(INPUT_JUMP, input, prev_input) {
                 if self.velocity.y < 0 {
-                    self.velocity.y = -SUPER_WALL_JUMP_POWER_Y;
+                    self.velocity.y = -ruleset.super_wall_jump_power_y;
                 }
                 self.velocity.x = if is_on_left_wall {
-                    SUPER_WALL_JUMP_POWER_X
+                    ruleset.super_wall_jump_power_x
                 } else {
-                    -SUPER_WALL_JUMP_POWER_X
+                    -ruleset.super_wall_jump_power_x
                 };
                 self.dodge_timer = 0;
                 self.is_wall_sliding = false;
                 self.is_super_jumping = true;
                 self.is_super_jumping_off_wall_slide = true;
+                self.make_burst_at_feet();
                 self.add_sound_command("superjump", "play", 100);
             }
         }
 
         self.move_by(
             level,
+            ruleset,
             self.velocity.x / OG_FPS,
             self.velocity.y / OG_FPS,
             true,
             is_on_ground,
             is_on_left_wall,
             is_on_right_wall,
-            other_player_hitbox,
-            other_boomerang_hitbox,
+            opponent_hurtboxes,
+            opponent_boomerang_hitboxes,
         );
     }
 
@@ -339,14 +369,23 @@ impl Player {
         &mut self,
         input: u8,
         prev_input: u8,
+        aim: u8,
         level: &Level,
+        ruleset: &Ruleset,
         is_on_ground: bool,
         is_on_left_wall: bool,
         is_on_right_wall: bool,
         is_on_wall: bool,
-        other_player_hitbox: &Hitbox,
-        other_boomerang_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        opponent_boomerang_hitboxes: &[(usize, Hitbox)],
     ) {
+        if input_pressed(INPUT_JUMP, input, prev_input) {
+            self.jump_buffer_timer = ruleset.jump_buffer + 1;
+        }
+        let is_jumping = self.jump_buffer_timer > 0;
+        self.jump_buffer_timer =
+            std::cmp::max(self.jump_buffer_timer - 1, 0);
+
         if input_pressed(INPUT_DODGE, input, prev_input)
             && self.dodge_timer == 0
             && self.dodge_cooldown == 0
@@ -371,25 +410,31 @@ impl Player {
             } else if input_check(INPUT_DOWN, input) {
                 dodge_heading.y = 1;
             }
+            if aim != NO_AIM {
+                dodge_heading = aim_heading(aim - 1, 1000);
+            }
 
             if is_on_ground && input_check(INPUT_DOWN, input) {
-                self.reset_dodge_timer(SLIDE_DURATION);
+                self.reset_dodge_timer(ruleset.slide_duration);
                 self.is_sliding = true;
             } else if is_on_left_wall && dodge_heading.x < 0
                 || is_on_right_wall && dodge_heading.x > 0
             {
                 dodge_heading.y *= 2;
-                self.reset_dodge_timer(DODGE_DURATION);
+                self.reset_dodge_timer(ruleset.dodge_duration);
                 self.is_wall_sliding = true;
             } else {
-                self.reset_dodge_timer(DODGE_DURATION);
+                self.reset_dodge_timer(ruleset.dodge_duration);
                 self.is_sliding = false;
             }
 
             // Normalize to dodge speed
             self.velocity = dodge_heading;
-            self.velocity.normalize(DODGE_SPEED);
+            self.velocity.normalize(ruleset.dodge_speed);
             self.can_dodge = false;
+            self.jump_buffer_timer = 0;
+            self.ground_coyote_timer = 0;
+            self.wall_coyote_timer = 0;
             self.add_sound_command("dodge", "play", 100);
             return;
         }
@@ -399,14 +444,22 @@ impl Player {
             self.is_super_jumping_off_wall_slide = false;
         }
 
-        let mut accel = if is_on_ground { RUN_ACCEL } else { AIR_ACCEL };
+        let mut accel = if is_on_ground {
+            ruleset.run_accel
+        } else {
+            ruleset.air_accel
+        };
         if is_on_ground
             && (input_check(INPUT_LEFT, input) && self.velocity.x > 0
                 || input_check(INPUT_RIGHT, input) && self.velocity.x < 0)
         {
-            accel *= RUN_ACCEL_TURN_MULTIPLIER;
+            accel *= ruleset.run_accel_turn_multiplier;
         }
-        let decel = if is_on_ground { RUN_DECEL } else { AIR_DECEL };
+        let decel = if is_on_ground {
+            ruleset.run_decel
+        } else {
+            ruleset.air_decel
+        };
         if input_check(INPUT_LEFT, input)
             && !input_check(INPUT_RIGHT, input)
             && !is_on_left_wall
@@ -422,15 +475,15 @@ impl Player {
         }
 
         let mut max_speed = if is_on_ground {
-            MAX_RUN_SPEED
+            ruleset.max_run_speed
         } else {
-            MAX_AIR_SPEED
+            ruleset.max_air_speed
         };
         if self.is_super_jumping {
             if self.is_super_jumping_off_wall_slide {
-                max_speed = MAX_SUPERJUMP_SPEED_X_OFF_WALL_SLIDE;
+                max_speed = ruleset.max_superjump_speed_x_off_wall_slide;
             } else {
-                max_speed = MAX_SUPERJUMP_SPEED_X;
+                max_speed = ruleset.max_superjump_speed_x;
             }
         }
         self.velocity.x = clamp(self.velocity.x, -max_speed, max_speed);
@@ -439,34 +492,39 @@ impl Player {
             self.can_double_jump = true;
             self.can_dodge = true;
             self.velocity.y = 0;
-            if input_pressed(INPUT_JUMP, input, prev_input) {
-                self.velocity.y = -JUMP_POWER;
-                self.make_dust_at_feet();
-                self.add_sound_command("jump", "play", 100);
+            self.ground_coyote_timer = ruleset.coyote_time;
+            if is_jumping {
+                self.jump(input, ruleset);
             }
         } else if is_on_wall {
             let gravity = if self.velocity.y > 0 {
-                GRAVITY_ON_WALL
+                ruleset.gravity_on_wall
             } else {
-                GRAVITY
+                ruleset.gravity
             };
             self.velocity.y += gravity / OG_FPS;
-            self.velocity.y =
-                std::cmp::min(self.velocity.y, MAX_FALL_SPEED_ON_WALL);
-            if input_pressed(INPUT_JUMP, input, prev_input) {
-                self.velocity.y = -WALL_JUMP_POWER_Y;
-                self.velocity.x = if is_on_left_wall {
-                    WALL_JUMP_POWER_X
-                } else {
-                    -WALL_JUMP_POWER_X
-                };
-                self.add_sound_command("jump", "play", 100);
+            self.velocity.y = std::cmp::min(
+                self.velocity.y,
+                ruleset.max_fall_speed_on_wall,
+            );
+            self.wall_coyote_timer = ruleset.coyote_time;
+            self.was_on_left_wall = is_on_left_wall;
+            if is_jumping {
+                self.wall_jump(input, ruleset);
             }
         } else {
-            if input_pressed(INPUT_JUMP, input, prev_input)
+            self.ground_coyote_timer =
+                std::cmp::max(self.ground_coyote_timer - 1, 0);
+            self.wall_coyote_timer =
+                std::cmp::max(self.wall_coyote_timer - 1, 0);
+            if is_jumping && self.ground_coyote_timer > 0 {
+                self.jump(input, ruleset);
+            } else if is_jumping && self.wall_coyote_timer > 0 {
+                self.wall_jump(input, ruleset);
+            } else if input_pressed(INPUT_JUMP, input, prev_input)
                 && self.can_double_jump
             {
-                self.velocity.y = -DOUBLE_JUMP_POWER_Y;
+                self.velocity.y = -ruleset.double_jump_power_y;
                 if self.velocity.x > 0 && input_check(INPUT_LEFT, input)
                     || self.velocity.x < 0
                         && input_check(INPUT_RIGHT, input)
@@ -474,23 +532,26 @@ impl Player {
                     self.velocity.x = 0;
                 }
                 self.can_double_jump = false;
+                self.jump_buffer_timer = 0;
                 self.make_dust_at_feet();
                 self.add_sound_command("doublejump", "play", 100);
             }
             if input_released(INPUT_JUMP, input, prev_input)
                 && !self.is_super_jumping
             {
-                self.velocity.y =
-                    std::cmp::max(self.velocity.y, -JUMP_CANCEL_POWER);
+                self.velocity.y = std::cmp::max(
+                    self.velocity.y,
+                    -ruleset.jump_cancel_power,
+                );
             }
-            let mut gravity = GRAVITY;
-            let mut max_fall_speed = MAX_FALL_SPEED;
+            let mut gravity = ruleset.gravity;
+            let mut max_fall_speed = ruleset.max_fall_speed;
             if input_check(INPUT_DOWN, input)
-                && self.velocity.y > -JUMP_CANCEL_POWER
+                && self.velocity.y > -ruleset.jump_cancel_power
                 && !self.is_super_jumping
             {
-                gravity = FASTFALL_GRAVITY;
-                max_fall_speed = MAX_FASTFALL_SPEED;
+                gravity = ruleset.fastfall_gravity;
+                max_fall_speed = ruleset.max_fastfall_speed;
             }
             self.velocity.y += gravity / OG_FPS;
             self.velocity.y =
@@ -501,17 +562,26 @@ impl Player {
         // when player is at tunneling velocity
         self.move_by(
             level,
+            ruleset,
             self.velocity.x / OG_FPS,
             self.velocity.y / OG_FPS,
             true,
             is_on_ground,
             is_on_left_wall,
             is_on_right_wall,
-            other_player_hitbox,
-            other_boomerang_hitbox,
+            opponent_hurtboxes,
+            opponent_boomerang_hitboxes,
         );
     }
 
+    // the first few frames of a dodge deflect boomerangs
+    pub fn is_parrying(&self, ruleset: &Ruleset) -> bool {
+        return self.dodge_timer > 0
+            && !self.is_sliding
+            && self.dodge_timer_duration - self.dodge_timer
+                < ruleset.parry_window;
+    }
+
     pub fn reset_dodge_timer(&mut self, new_duration: i32) {
         self.dodge_timer = new_duration;
         self.dodge_timer_duration = new_duration;
@@ -525,6 +595,40 @@ impl Player {
         }
     }
 
+    fn jump(&mut self, input: u8, ruleset: &Ruleset) {
+        self.velocity.y = -ruleset.jump_power;
+        self.jump_buffer_timer = 0;
+        self.ground_coyote_timer = 0;
+        self.wall_coyote_timer = 0;
+        self.cancel_released_jump(input, ruleset);
+        self.make_dust_at_feet();
+        self.add_sound_command("jump", "play", 100);
+    }
+
+    // jumps away from the wall we're on or just left
+    fn wall_jump(&mut self, input: u8, ruleset: &Ruleset) {
+        self.velocity.y = -ruleset.wall_jump_power_y;
+        self.velocity.x = if self.was_on_left_wall {
+            ruleset.wall_jump_power_x
+        } else {
+            -ruleset.wall_jump_power_x
+        };
+        self.jump_buffer_timer = 0;
+        self.ground_coyote_timer = 0;
+        self.wall_coyote_timer = 0;
+        self.cancel_released_jump(input, ruleset);
+        self.add_sound_command("jump", "play", 100);
+    }
+
+    // a buffered jump whose button was let go before it came out is cut
+    // short straight away, the same as letting go mid-jump
+    fn cancel_released_jump(&mut self, input: u8, ruleset: &Ruleset) {
+        if !input_check(INPUT_JUMP, input) {
+            self.velocity.y =
+                std::cmp::max(self.velocity.y, -ruleset.jump_cancel_power);
+        }
+    }
+
     pub fn make_dust_at_feet(&mut self) {
         self.particle_spawns.push((
             IntVector2D {
@@ -535,17 +639,43 @@ impl Player {
         ));
     }
 
+    fn make_burst_at_feet(&mut self) {
+        self.particle_spawns.push((
+            IntVector2D {
+                x: self.hitbox.x + self.hitbox.width / 2,
+                y: self.hitbox.y + self.hitbox.height,
+            },
+            "superjump".to_string(),
+        ));
+    }
+
+    fn make_sparks_on_wall(&mut self, is_on_left_wall: bool) {
+        let (x, emitter) = if is_on_left_wall {
+            (self.hitbox.x, "leftwallsparks")
+        } else {
+            (self.hitbox.x + self.hitbox.width, "rightwallsparks")
+        };
+        self.particle_spawns.push((
+            IntVector2D {
+                x,
+                y: self.hitbox.y + self.hitbox.height,
+            },
+            emitter.to_string(),
+        ));
+    }
+
     pub fn move_by(
         &mut self,
         level: &Level,
+        ruleset: &Ruleset,
         move_x: i32,
         move_y: i32,
         sweep: bool,
         is_on_ground: bool,
         is_on_left_wall: bool,
         is_on_right_wall: bool,
-        other_player_hitbox: &Hitbox,
-        other_boomerang_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        opponent_boomerang_hitboxes: &[(usize, Hitbox)],
     ) {
         let mut collided_on_x = false;
         if sweep
@@ -569,8 +699,8 @@ impl Player {
                             increments[increment_index] * sign;
                         move_amount -= increments[increment_index];
                         self.check_entity_collisions(
-                            other_player_hitbox,
-                            other_boomerang_hitbox,
+                            opponent_hurtboxes,
+                            opponent_boomerang_hitboxes,
                         );
                     }
                 }
@@ -582,6 +712,7 @@ impl Player {
 
         if collided_on_x {
             self.move_collide_x(
+                ruleset,
                 is_on_ground,
                 is_on_left_wall,
                 is_on_right_wall,
@@ -610,8 +741,8 @@ impl Player {
                             increments[increment_index] * sign;
                         move_amount -= increments[increment_index];
                         self.check_entity_collisions(
-                            other_player_hitbox,
-                            other_boomerang_hitbox,
+                            opponent_hurtboxes,
+                            opponent_boomerang_hitboxes,
                         );
                     }
                 }
@@ -624,26 +755,36 @@ impl Player {
             self.move_collide_y();
         }
         self.check_entity_collisions(
-            other_player_hitbox,
-            other_boomerang_hitbox,
+            opponent_hurtboxes,
+            opponent_boomerang_hitboxes,
         );
     }
 
     pub fn check_entity_collisions(
         &mut self,
-        other_player_hitbox: &Hitbox,
-        other_boomerang_hitbox: &Hitbox,
+        opponent_hurtboxes: &[(usize, Hitbox)],
+        opponent_boomerang_hitboxes: &[(usize, Hitbox)],
     ) {
-        if do_hitboxes_overlap(&self.hitbox, other_player_hitbox) {
-            self.collided_with_player = true;
+        let hurtbox = self.hurtbox();
+        for (player_num, hitbox) in opponent_hurtboxes {
+            if do_hitboxes_overlap(&hurtbox, hitbox)
+                && !self.collided_with_players.contains(player_num)
+            {
+                self.collided_with_players.push(*player_num);
+            }
         }
-        if do_hitboxes_overlap(&self.hitbox, other_boomerang_hitbox) {
-            self.collided_with_boomerang = true;
+        for (player_num, hitbox) in opponent_boomerang_hitboxes {
+            if do_hitboxes_overlap(&hurtbox, hitbox)
+                && !self.collided_with_boomerangs.contains(player_num)
+            {
+                self.collided_with_boomerangs.push(*player_num);
+            }
         }
     }
 
     pub fn move_collide_x(
         &mut self,
+        ruleset: &Ruleset,
         is_on_ground: bool,
         is_on_left_wall: bool,
         is_on_right_wall: bool,
@@ -652,13 +793,13 @@ impl Player {
             self.velocity.x = 0;
         } else if is_on_left_wall {
             self.velocity.x =
-                std::cmp::max(self.velocity.x, -WALL_STICKINESS);
+                std::cmp::max(self.velocity.x, -ruleset.wall_stickiness);
             if self.dodge_timer > 0 {
                 self.is_wall_sliding = true;
             }
         } else if is_on_right_wall {
             self.velocity.x =
-                std::cmp::min(self.velocity.x, WALL_STICKINESS);
+                std::cmp::min(self.velocity.x, ruleset.wall_stickiness);
             if self.dodge_timer > 0 {
                 self.is_wall_sliding = true;
             }
@@ -681,29 +822,29 @@ impl Player {
             width: self.hitbox.width,
             height: self.hitbox.height,
         };
-        let tile_x = virtual_x / TILE_SIZE;
-        let tile_y = virtual_y / TILE_SIZE;
-        // We use (dividend + divisor - 1) / divisor here
-        // to get integer division that rounds up
-        let tile_width = (player_hitbox.width + TILE_SIZE - 1) / TILE_SIZE;
-        let tile_height =
-            (player_hitbox.height + TILE_SIZE - 1) / TILE_SIZE;
-        for check_x in 0..(tile_width + 1) {
-            for check_y in 0..(tile_height + 1) {
-                if level.check_grid(tile_x + check_x, tile_y + check_y) {
-                    let grid_hitbox = Hitbox {
-                        x: (tile_x + check_x) * TILE_SIZE,
-                        y: (tile_y + check_y) * TILE_SIZE,
-                        width: TILE_SIZE,
-                        height: TILE_SIZE,
-                    };
-                    if do_hitboxes_overlap(&player_hitbox, &grid_hitbox) {
-                        return true;
-                    }
-                }
-            }
+        return level.collide(&player_hitbox);
+    }
+
+    // hurtbox offsets are for facing right, so mirror them when facing
+    // left
+    pub fn hurtbox(&self) -> Hitbox {
+        let mut offset_x = self.hurtbox_offset.x;
+        if self.is_facing_left {
+            offset_x = self.hitbox.width
+                - self.hurtbox_offset.x
+                - self.hurtbox_offset.width;
         }
-        return false;
+        return Hitbox {
+            x: self.hitbox.x + offset_x,
+            y: self.hitbox.y + self.hurtbox_offset.y,
+            width: self.hurtbox_offset.width,
+            height: self.hurtbox_offset.height,
+        };
+    }
+
+    // dead and waiting to respawn still counts as in the round
+    pub fn is_out(&self) -> bool {
+        return self.is_dead && self.respawn_timer == 0;
     }
 
     pub fn center_x(&self) -> i32 {
@@ -727,3 +868,105 @@ impl Player {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forgiving_ruleset() -> Ruleset {
+        return Ruleset {
+            jump_buffer: 4,
+            coyote_time: 5,
+            ..Ruleset::default()
+        };
+    }
+
+    // a player at the first start who has used up their double jump, so
+    // pressing jump in the air does nothing by itself
+    fn new_player(level: &Level) -> Player {
+        let start = level.player_starts[0];
+        let mut player = Player::new(start.x, start.y - 1, false);
+        player.can_double_jump = false;
+        return player;
+    }
+
+    // one frame of movement, either on the ground or in the air
+    fn step(
+        player: &mut Player,
+        inputs: (u8, u8),
+        is_on_ground: bool,
+        level: &Level,
+        ruleset: &Ruleset,
+    ) {
+        player.movement(
+            inputs.0,
+            inputs.1,
+            NO_AIM,
+            level,
+            ruleset,
+            is_on_ground,
+            false,
+            false,
+            false,
+            &[],
+            &[],
+        );
+    }
+
+    #[test]
+    fn a_jump_pressed_just_before_landing_comes_out_on_landing() {
+        let level = Level::new();
+        for (ruleset, is_buffered) in
+            [(Ruleset::default(), false), (forgiving_ruleset(), true)]
+        {
+            let mut player = new_player(&level);
+            step(&mut player, (INPUT_JUMP, 0), false, &level, &ruleset);
+            step(
+                &mut player,
+                (INPUT_JUMP, INPUT_JUMP),
+                true,
+                &level,
+                &ruleset,
+            );
+            let expected =
+                if is_buffered { -ruleset.jump_power } else { 0 };
+            assert_eq!(player.velocity.y, expected);
+        }
+    }
+
+    #[test]
+    fn a_jump_just_after_leaving_a_ledge_is_a_ground_jump() {
+        let level = Level::new();
+        let ruleset = forgiving_ruleset();
+        let mut player = new_player(&level);
+        player.can_double_jump = true;
+        step(&mut player, (0, 0), true, &level, &ruleset);
+        step(&mut player, (INPUT_JUMP, 0), false, &level, &ruleset);
+        // gravity still applies on the frame of a jump in the air
+        let gravity = ruleset.gravity / OG_FPS;
+        assert_eq!(player.velocity.y, -ruleset.jump_power + gravity);
+        assert!(player.can_double_jump);
+
+        // without coyote time it's the double jump instead
+        let ruleset = Ruleset::default();
+        let mut player = new_player(&level);
+        player.can_double_jump = true;
+        step(&mut player, (0, 0), true, &level, &ruleset);
+        step(&mut player, (INPUT_JUMP, 0), false, &level, &ruleset);
+        assert_eq!(
+            player.velocity.y,
+            -ruleset.double_jump_power_y + gravity
+        );
+        assert!(!player.can_double_jump);
+    }
+
+    #[test]
+    fn a_buffered_jump_let_go_before_landing_is_cut_short() {
+        let level = Level::new();
+        let ruleset = forgiving_ruleset();
+        let mut player = new_player(&level);
+        step(&mut player, (INPUT_JUMP, 0), false, &level, &ruleset);
+        step(&mut player, (0, INPUT_JUMP), true, &level, &ruleset);
+        assert_eq!(player.velocity.y, -ruleset.jump_cancel_power);
+    }
+}
@@ -0,0 +1,193 @@
+use tetra::math::Vec2;
+
+use crate::game::State;
+
+// what's left of a rollback correction after each frame
+const CORRECTION_DECAY: f32 = 0.7;
+// corrections farther than this in pixels are shown straight away
+const SNAP_DISTANCE: f32 = 24.0;
+
+// where one player or boomerang is drawn, in screen pixels
+#[derive(Clone)]
+struct View {
+    simulated: Vec2<f32>,
+    // the part of a rollback correction still to be caught up on
+    correction: Vec2<f32>,
+    previous: Vec2<f32>,
+    current: Vec2<f32>,
+}
+
+impl View {
+    fn new(position: Vec2<f32>) -> View {
+        return View {
+            simulated: position,
+            correction: Vec2::zero(),
+            previous: position,
+            current: position,
+        };
+    }
+
+    // previous is where the simulation had it before this frame
+    fn advance(
+        &mut self,
+        position: Vec2<f32>,
+        previous: Vec2<f32>,
+        was_rolled_back: bool,
+    ) {
+        // the difference between where the last frame was drawn and
+        // where the rollback says it really was
+        if was_rolled_back {
+            self.correction += self.simulated - previous;
+        }
+        self.simulated = position;
+        self.correction *= CORRECTION_DECAY;
+        if self.correction.magnitude() > SNAP_DISTANCE
+            || self.correction.magnitude() < 0.1
+        {
+            self.correction = Vec2::zero();
+        }
+        self.previous = self.current;
+        self.current = position + self.correction;
+        // teleports, like respawns, aren't drawn in between
+        if self.current.distance(self.previous) > SNAP_DISTANCE {
+            self.previous = self.current;
+        }
+    }
+
+    fn position(&self, alpha: f32) -> Vec2<f32> {
+        return Vec2::lerp(self.previous, self.current, alpha);
+    }
+}
+
+// smooths out what's drawn of the simulation. small corrections from
+// rollbacks are eased in over a few frames instead of snapping, and
+// positions are interpolated between frames for displays faster than
+// the simulation
+pub struct Presentation {
+    players: Vec<View>,
+    boomerangs: Vec<View>,
+    // how far the display is between the last two frames, from 0 to 1
+    alpha: f32,
+}
+
+impl Presentation {
+    pub fn new(state: &State) -> Presentation {
+        let (players, boomerangs) = get_positions(state);
+        return Presentation {
+            players: players
+                .into_iter()
+                .map(|(position, _)| View::new(position))
+                .collect(),
+            boomerangs: boomerangs
+                .into_iter()
+                .map(|(position, _)| View::new(position))
+                .collect(),
+            alpha: 1.0,
+        };
+    }
+
+    // called once per simulated frame
+    pub fn advance(&mut self, state: &State, was_rolled_back: bool) {
+        let (players, boomerangs) = get_positions(state);
+        for (view, (position, previous)) in
+            self.players.iter_mut().zip(players)
+        {
+            view.advance(position, previous, was_rolled_back);
+        }
+        for (view, (position, previous)) in
+            self.boomerangs.iter_mut().zip(boomerangs)
+        {
+            view.advance(position, previous, was_rolled_back);
+        }
+    }
+
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = alpha.clamp(0.0, 1.0);
+    }
+
+    pub fn player_position(&self, player_num: usize) -> Vec2<f32> {
+        return self.players[player_num].position(self.alpha);
+    }
+
+    pub fn boomerang_position(&self, player_num: usize) -> Vec2<f32> {
+        return self.boomerangs[player_num].position(self.alpha);
+    }
+}
+
+type Positions = Vec<(Vec2<f32>, Vec2<f32>)>;
+
+// the center of every player and boomerang, and where it was before the
+// last frame
+fn get_positions(state: &State) -> (Positions, Positions) {
+    let players = state
+        .players
+        .iter()
+        .zip(&state.prev_player_centers)
+        .map(|(player, previous)| {
+            (
+                to_screen(player.center_x(), player.center_y()),
+                to_screen(previous.x, previous.y),
+            )
+        })
+        .collect();
+    let boomerangs = state
+        .boomerangs
+        .iter()
+        .zip(&state.prev_boomerang_centers)
+        .map(|(boomerang, previous)| {
+            (
+                to_screen(boomerang.center_x(), boomerang.center_y()),
+                to_screen(previous.x, previous.y),
+            )
+        })
+        .collect();
+    return (players, boomerangs);
+}
+
+fn to_screen(x: i32, y: i32) -> Vec2<f32> {
+    return Vec2::new(x as f32 / 1000.0, y as f32 / 1000.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Vec2<f32> {
+        return Vec2::new(x, 0.0);
+    }
+
+    #[test]
+    fn frames_are_drawn_in_between() {
+        let mut view = View::new(at(0.0));
+        view.advance(at(10.0), at(0.0), false);
+        assert_eq!(view.position(0.0), at(0.0));
+        assert_eq!(view.position(0.5), at(5.0));
+        assert_eq!(view.position(1.0), at(10.0));
+    }
+
+    #[test]
+    fn small_corrections_are_eased_in() {
+        let mut view = View::new(at(0.0));
+        // the rollback says it was really at 5 the frame before
+        view.advance(at(10.0), at(5.0), true);
+        assert_eq!(view.position(1.0), at(10.0 - 5.0 * CORRECTION_DECAY));
+
+        let mut frames = 1;
+        while view.correction != Vec2::zero() {
+            view.advance(at(10.0), at(10.0), false);
+            frames += 1;
+            assert!(frames < 20);
+        }
+        assert_eq!(view.position(1.0), at(10.0));
+        assert!(frames > 5);
+    }
+
+    #[test]
+    fn large_corrections_snap() {
+        let mut view = View::new(at(0.0));
+        view.advance(at(100.0), at(70.0), true);
+        assert_eq!(view.correction, Vec2::zero());
+        // and it isn't drawn sweeping across the screen either
+        assert_eq!(view.position(0.0), at(100.0));
+    }
+}
@@ -0,0 +1,379 @@
+use quick_xml::de::from_str;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+
+use crate::utils::fnv1a_hash;
+
+pub const DEFAULT_RULESET: &str = "classic";
+const RULESETS_PATH: &str = "./resources/rulesets";
+
+// what a thrown boomerang does when it reaches a solid tile
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum WallBehavior {
+    Pass,
+    Bounce,
+    Stick,
+}
+
+// quick-xml reads enums from element names, but ruleset files write
+// the variant as text
+impl<'de> Deserialize<'de> for WallBehavior {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return match name.trim() {
+            "pass" => Ok(WallBehavior::Pass),
+            "bounce" => Ok(WallBehavior::Bounce),
+            "stick" => Ok(WallBehavior::Stick),
+            _ => Err(D::Error::custom(format!(
+                "unknown wall behavior {}, expected pass, bounce or stick",
+                name
+            ))),
+        };
+    }
+}
+
+// what happens when the last players standing all die on the same frame
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum TradeRule {
+    // nobody scores
+    Draw,
+    // every team that traded scores
+    BothScore,
+    // nobody scores and the round is replayed straight away as sudden
+    // death
+    SuddenDeath,
+}
+
+impl<'de> Deserialize<'de> for TradeRule {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return match name.trim() {
+            "draw" => Ok(TradeRule::Draw),
+            "both_score" => Ok(TradeRule::BothScore),
+            "sudden_death" => Ok(TradeRule::SuddenDeath),
+            _ => Err(D::Error::custom(format!(
+                "unknown trade rule {}, expected draw, both_score or \
+                 sudden_death",
+                name
+            ))),
+        };
+    }
+}
+
+// what makes a round end once its time is up
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum SuddenDeath {
+    // boomerangs are thrown faster and faster
+    SpeedUp,
+    // the edges of the level fill in with solid tiles, crushing anyone
+    // caught in them
+    CloseIn,
+}
+
+impl<'de> Deserialize<'de> for SuddenDeath {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return match name.trim() {
+            "speed_up" => Ok(SuddenDeath::SpeedUp),
+            "close_in" => Ok(SuddenDeath::CloseIn),
+            _ => Err(D::Error::custom(format!(
+                "unknown sudden death {}, expected speed_up or close_in",
+                name
+            ))),
+        };
+    }
+}
+
+// Distances are in thousandths of a pixel, so speeds are thousandths of
+// a pixel per second and accelerations per second squared. Durations
+// are in frames. Fields missing from a ruleset file keep their classic
+// value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "ruleset", default)]
+pub struct Ruleset {
+    pub run_accel: i32,
+    pub run_accel_turn_multiplier: i32,
+    pub run_decel: i32,
+    pub air_accel: i32,
+    pub air_decel: i32,
+    pub max_run_speed: i32,
+    pub max_superjump_speed_x: i32,
+    pub max_superjump_speed_x_off_wall_slide: i32,
+    pub max_air_speed: i32,
+    pub gravity: i32,
+    pub fastfall_gravity: i32,
+    pub gravity_on_wall: i32,
+    pub jump_power: i32,
+    pub jump_cancel_power: i32,
+    pub wall_jump_power_x: i32,
+    pub wall_jump_power_y: i32,
+    pub super_wall_jump_power_x: i32,
+    pub super_wall_jump_power_y: i32,
+    pub wall_stickiness: i32,
+    pub max_fall_speed: i32,
+    pub max_fall_speed_on_wall: i32,
+    pub max_fastfall_speed: i32,
+    pub double_jump_power_y: i32,
+    pub dodge_duration: i32,
+    pub slide_duration: i32,
+    pub slide_decel: i32,
+    pub dodge_cooldown: i32,
+    pub dodge_speed: i32,
+    // a jump pressed this many frames before landing or touching a wall
+    // still happens once there
+    pub jump_buffer: i32,
+    // how many frames after leaving the ground or a wall jumping still
+    // counts as a ground or wall jump
+    pub coyote_time: i32,
+    pub boomerang_max_speed: i32,
+    // how quickly a thrown boomerang turns back, in thousandths
+    pub boomerang_return_rate: i32,
+    // holding attack charges a throw through this many levels, or throws
+    // right away on the press when 0
+    pub boomerang_charge_levels: i32,
+    pub boomerang_frames_per_charge_level: i32,
+    // speed and return rate of a throw at the last charge level
+    pub boomerang_charged_max_speed: i32,
+    pub boomerang_charged_return_rate: i32,
+    // pressing attack with the boomerang out calls it back
+    pub boomerang_manual_recall: bool,
+    pub boomerang_wall_behavior: WallBehavior,
+    // how many frames into a dodge it still parries boomerangs
+    pub parry_window: i32,
+    // players 1 and 3 against players 2 and 4 instead of free-for-all
+    pub teams: bool,
+    // whether teammates can kill each other
+    pub friendly_fire: bool,
+    pub trade_rule: TradeRule,
+    // frames before sudden death starts, or 0 for rounds without a timer
+    pub round_time: i32,
+    pub sudden_death: SuddenDeath,
+    // how much faster boomerangs are thrown per second of sudden death,
+    // up to the max
+    pub sudden_death_speedup: i32,
+    pub sudden_death_max_speedup: i32,
+    // frames between each ring of tiles closing in
+    pub sudden_death_close_interval: i32,
+    // lives per round, so 1 is one hit and out
+    pub stocks: i32,
+    // frames before a player with stocks left comes back, and frames
+    // they can't be hurt for after
+    pub respawn_delay: i32,
+    pub respawn_invulnerability: i32,
+    // frames the world freezes for when someone dies
+    pub hitstop: i32,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        return Ruleset {
+            run_accel: 400 * 1000,
+            run_accel_turn_multiplier: 2,
+            run_decel: 800 * 1000,
+            air_accel: 360 * 1000,
+            air_decel: 360 * 1000,
+            max_run_speed: 100 * 1000,
+            max_superjump_speed_x: 250 * 1000,
+            max_superjump_speed_x_off_wall_slide: 150 * 1000,
+            max_air_speed: 120 * 1000,
+            gravity: 500 * 1000,
+            fastfall_gravity: 1200 * 1000,
+            gravity_on_wall: 150 * 1000,
+            jump_power: 160 * 1000,
+            jump_cancel_power: 40 * 1000,
+            wall_jump_power_x: 130 * 1000,
+            wall_jump_power_y: 120 * 1000,
+            super_wall_jump_power_x: 74286,
+            super_wall_jump_power_y: 210 * 1000,
+            wall_stickiness: 60 * 1000,
+            max_fall_speed: 270 * 1000,
+            max_fall_speed_on_wall: 200 * 1000,
+            max_fastfall_speed: 500 * 1000,
+            double_jump_power_y: 130 * 1000,
+            dodge_duration: 9,
+            slide_duration: 19,
+            slide_decel: 100 * 1000,
+            dodge_cooldown: 9,
+            dodge_speed: 260 * 1000,
+            jump_buffer: 0,
+            coyote_time: 0,
+            boomerang_max_speed: 300 * 1000,
+            boomerang_return_rate: 750,
+            boomerang_charge_levels: 0,
+            boomerang_frames_per_charge_level: 20,
+            boomerang_charged_max_speed: 380 * 1000,
+            boomerang_charged_return_rate: 550,
+            boomerang_manual_recall: false,
+            boomerang_wall_behavior: WallBehavior::Pass,
+            parry_window: 0,
+            teams: false,
+            friendly_fire: false,
+            trade_rule: TradeRule::Draw,
+            round_time: 0,
+            sudden_death: SuddenDeath::CloseIn,
+            sudden_death_speedup: 20 * 1000,
+            sudden_death_max_speedup: 200 * 1000,
+            sudden_death_close_interval: 60,
+            stocks: 1,
+            respawn_delay: 60,
+            respawn_invulnerability: 90,
+            hitstop: 0,
+        };
+    }
+}
+
+impl Ruleset {
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = format!("{}/{}.xml", RULESETS_PATH, name);
+        let xml = fs::read_to_string(&path)
+            .map_err(|err| format!("could not read {}: {}", path, err))?;
+        return from_str(&xml)
+            .map_err(|err| format!("could not parse {}: {}", path, err));
+    }
+
+    // names of every ruleset file, sorted so all peers list them alike
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = match fs::read_dir(RULESETS_PATH) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let path = entry.path();
+                    if path.extension()? != "xml" {
+                        return None;
+                    }
+                    Some(path.file_stem()?.to_str()?.to_string())
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        names.sort();
+        return names;
+    }
+
+    pub fn team(&self, player_num: usize) -> usize {
+        if self.teams {
+            return player_num % 2;
+        }
+        return player_num;
+    }
+
+    pub fn can_hurt(&self, attacker: usize, victim: usize) -> bool {
+        if attacker == victim {
+            return false;
+        }
+        return self.team(attacker) != self.team(victim)
+            || self.friendly_fire;
+    }
+
+    pub fn hash(&self) -> u64 {
+        return fnv1a_hash(&bincode::serialize(self).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_classic_file_matches_the_defaults() {
+        assert_eq!(Ruleset::load(DEFAULT_RULESET), Ok(Ruleset::default()));
+    }
+
+    #[test]
+    fn missing_fields_keep_their_classic_value() {
+        let ruleset: Ruleset = from_str(
+            "<ruleset><jump_power>1</jump_power>\
+             <dodge_speed>2</dodge_speed></ruleset>",
+        )
+        .unwrap();
+        assert_eq!(
+            ruleset,
+            Ruleset {
+                jump_power: 1,
+                dodge_speed: 2,
+                ..Ruleset::default()
+            }
+        );
+    }
+
+    #[test]
+    fn wall_behaviors_are_read_by_name() {
+        let ruleset: Ruleset = from_str(
+            "<ruleset>\
+             <boomerang_wall_behavior> stick </boomerang_wall_behavior>\
+             </ruleset>",
+        )
+        .unwrap();
+        assert_eq!(ruleset.boomerang_wall_behavior, WallBehavior::Stick);
+        let result: Result<Ruleset, _> = from_str(
+            "<ruleset>\
+             <boomerang_wall_behavior>wobble</boomerang_wall_behavior>\
+             </ruleset>",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn trade_rules_are_read_by_name() {
+        let ruleset: Ruleset = from_str(
+            "<ruleset><trade_rule>both_score</trade_rule></ruleset>",
+        )
+        .unwrap();
+        assert_eq!(ruleset.trade_rule, TradeRule::BothScore);
+        let result: Result<Ruleset, _> = from_str(
+            "<ruleset><trade_rule>coin_flip</trade_rule></ruleset>",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn free_for_all_puts_everyone_on_their_own_team() {
+        let ruleset = Ruleset::default();
+        for attacker in 0..4 {
+            for victim in 0..4 {
+                assert_eq!(
+                    ruleset.can_hurt(attacker, victim),
+                    attacker != victim
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn teams_pair_up_alternate_players() {
+        let ruleset = Ruleset {
+            teams: true,
+            ..Ruleset::default()
+        };
+        assert_eq!(
+            (0..4)
+                .map(|player| ruleset.team(player))
+                .collect::<Vec<_>>(),
+            vec![0, 1, 0, 1]
+        );
+        assert!(ruleset.can_hurt(0, 1));
+        assert!(!ruleset.can_hurt(0, 2));
+        let friendly_fire = Ruleset {
+            friendly_fire: true,
+            ..ruleset
+        };
+        assert!(friendly_fire.can_hurt(0, 2));
+        assert!(!friendly_fire.can_hurt(0, 0));
+    }
+
+    #[test]
+    fn every_ruleset_file_loads() {
+        let names = Ruleset::available();
+        assert!(names.contains(&DEFAULT_RULESET.to_string()));
+        for name in names {
+            assert!(Ruleset::load(&name).is_ok(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use quick_xml::de::from_str;
+use quick_xml::se::to_string;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::ruleset::DEFAULT_RULESET;
+
+const SETTINGS_PATH: &str = "./settings.xml";
+
+pub const MAX_WINDOW_SCALE: i32 = 6;
+pub const MAX_VOLUME: i32 = 100;
+pub const VOLUME_STEP: i32 = 10;
+
+// missing fields fall back to their defaults, so older files still load
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "settings", default)]
+pub struct Settings {
+    pub fullscreen: bool,
+    pub window_scale: i32,
+    pub integer_scaling: bool,
+    pub vsync: bool,
+    pub master_volume: i32,
+    pub sfx_volume: i32,
+    pub music_volume: i32,
+    // screen shake and flashing, which some players need turned off
+    pub screen_effects: bool,
+    // None picks the delay from the measured ping
+    pub input_delay: Option<usize>,
+    // a file name in resources/rulesets, without the extension
+    pub ruleset: String,
+    // a skin from resources/palettes.xml, or empty for the player's
+    // default
+    pub skin: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        return Settings {
+            fullscreen: true,
+            window_scale: 2,
+            integer_scaling: false,
+            vsync: false,
+            master_volume: MAX_VOLUME,
+            sfx_volume: MAX_VOLUME,
+            music_volume: MAX_VOLUME,
+            screen_effects: true,
+            input_delay: None,
+            ruleset: DEFAULT_RULESET.to_string(),
+            skin: String::new(),
+        };
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let xml = match fs::read_to_string(SETTINGS_PATH) {
+            Ok(xml) => xml,
+            Err(_) => return Settings::default(),
+        };
+        return match from_str(&xml) {
+            Ok(settings) => settings,
+            Err(err) => {
+                println!("ignoring {}: {}", SETTINGS_PATH, err);
+                Settings::default()
+            }
+        };
+    }
+
+    pub fn save(&self) {
+        let xml = to_string(self).unwrap();
+        if let Err(err) = fs::write(SETTINGS_PATH, xml) {
+            println!("could not save {}: {}", SETTINGS_PATH, err);
+        }
+    }
+
+    pub fn window_size(&self) -> (i32, i32) {
+        return (320 * self.window_scale, 180 * self.window_scale);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        return self.master_volume as f32 / MAX_VOLUME as f32;
+    }
+
+    // master volume is applied by the audio device on top of this
+    pub fn sfx_volume(&self) -> f32 {
+        return self.sfx_volume as f32 / MAX_VOLUME as f32;
+    }
+}
@@ -0,0 +1,218 @@
+use ggrs::PlayerHandle;
+use tetra::input::{self, Key};
+use tetra::Context;
+
+use crate::game::{
+    get_aim, Game, Input, INPUT_ATTACK, INPUT_JUMP, NO_AIM,
+};
+use crate::utils::IntVector2D;
+
+// the player who is being trained, the rest are dummies
+const TRAINEE: PlayerHandle = 0;
+// how often the dummy jumps, and how long it holds jump for
+const JUMP_PERIOD: i32 = 50;
+const JUMP_HOLD_FRAMES: i32 = 12;
+// how often the dummy throws, if its boomerang is back by then
+const THROW_PERIOD: i32 = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+enum DummyMode {
+    Idle,
+    Jump,
+    Throw,
+    Playback,
+}
+
+// an offline match against dummies, for practising against the same
+// thing over and over. the dummies can stand still, jump or throw at
+// the trainee on a loop, or replay inputs the trainee recorded while
+// controlling them. everyone can be put back at saved spots at any time
+pub struct Training {
+    dummy_handles: Vec<PlayerHandle>,
+    mode: DummyMode,
+    is_recording: bool,
+    recording: Vec<Input>,
+    // the frame the recording last started playing from
+    playback_start: i32,
+    spots: Vec<IntVector2D>,
+}
+
+impl Training {
+    pub fn new(game: &Game) -> Training {
+        return Training {
+            dummy_handles: (0..game.state.players.len())
+                .filter(|handle| *handle != TRAINEE)
+                .collect(),
+            mode: DummyMode::Idle,
+            is_recording: false,
+            recording: Vec::new(),
+            playback_start: 0,
+            spots: get_spots(game),
+        };
+    }
+
+    // F1 changes what the dummies do, F2 starts and stops recording,
+    // F5 puts everyone back at their spots and F6 saves where everyone
+    // is now as the new spots
+    pub fn update(&mut self, ctx: &mut Context, game: &mut Game) {
+        if input::is_key_pressed(ctx, Key::F1) && !self.is_recording {
+            self.mode = match self.mode {
+                DummyMode::Idle => DummyMode::Jump,
+                DummyMode::Jump => DummyMode::Throw,
+                DummyMode::Throw if !self.recording.is_empty() => {
+                    DummyMode::Playback
+                }
+                _ => DummyMode::Idle,
+            };
+            self.playback_start = game.state.frame;
+        }
+        if input::is_key_pressed(ctx, Key::F2) {
+            self.is_recording = !self.is_recording;
+            if self.is_recording {
+                self.recording.clear();
+            } else if !self.recording.is_empty() {
+                self.mode = DummyMode::Playback;
+            }
+            self.playback_start = game.state.frame;
+        }
+        if input::is_key_pressed(ctx, Key::F6) {
+            self.spots = get_spots(game);
+        }
+        // nobody else is in an offline match, so the state can be
+        // changed between frames without a rollback undoing it
+        if input::is_key_pressed(ctx, Key::F5) {
+            game.state.place_players(&self.spots);
+            self.playback_start = game.state.frame;
+        }
+    }
+
+    pub fn input(
+        &mut self,
+        ctx: &mut Context,
+        game: &Game,
+        handle: PlayerHandle,
+    ) -> Input {
+        let idle = Input {
+            inp: 0,
+            aim: NO_AIM,
+        };
+        // while recording, the trainee's controls move the dummies
+        // instead, and the trainee stands still
+        if !self.dummy_handles.contains(&handle) {
+            if self.is_recording {
+                return idle;
+            }
+            return game.local_input(ctx, handle);
+        }
+        if self.is_recording {
+            let input = game.local_input(ctx, TRAINEE);
+            if handle == self.dummy_handles[0] {
+                self.recording.push(input);
+            }
+            return input;
+        }
+        let frame = game.state.frame - self.playback_start;
+        return match self.mode {
+            DummyMode::Idle => idle,
+            DummyMode::Jump if frame % JUMP_PERIOD < JUMP_HOLD_FRAMES => {
+                Input {
+                    inp: INPUT_JUMP,
+                    aim: NO_AIM,
+                }
+            }
+            DummyMode::Jump => idle,
+            DummyMode::Throw
+                if frame % THROW_PERIOD == 0
+                    && game.state.boomerangs[handle].is_holstered =>
+            {
+                Input {
+                    inp: INPUT_ATTACK,
+                    aim: get_aim_at_trainee(game, handle),
+                }
+            }
+            DummyMode::Throw => idle,
+            DummyMode::Playback => self.get_recorded_input(frame),
+        };
+    }
+
+    // the recording loops for as long as it's played back
+    fn get_recorded_input(&self, frame: i32) -> Input {
+        return self.recording[frame as usize % self.recording.len()];
+    }
+
+    pub fn get_status_text(&self) -> String {
+        if self.is_recording {
+            return format!("RECORDING {}", self.recording.len());
+        }
+        let mode = match self.mode {
+            DummyMode::Idle => "IDLE",
+            DummyMode::Jump => "JUMP",
+            DummyMode::Throw => "THROW",
+            DummyMode::Playback => "PLAYBACK",
+        };
+        return format!(
+            "DUMMY {}  F1 MODE  F2 RECORD  F5 RESET  F6 SAVE",
+            mode
+        );
+    }
+}
+
+// where every player is now
+fn get_spots(game: &Game) -> Vec<IntVector2D> {
+    return game
+        .state
+        .players
+        .iter()
+        .map(|player| IntVector2D {
+            x: player.hitbox.x,
+            y: player.hitbox.y,
+        })
+        .collect();
+}
+
+fn get_aim_at_trainee(game: &Game, handle: PlayerHandle) -> u8 {
+    let dummy = &game.state.players[handle];
+    let trainee = &game.state.players[TRAINEE];
+    return get_aim(
+        (trainee.center_x() - dummy.center_x()) as f32,
+        (trainee.center_y() - dummy.center_y()) as f32,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playback_loops_the_recording() {
+        let training = Training {
+            dummy_handles: vec![1],
+            mode: DummyMode::Playback,
+            is_recording: false,
+            recording: [INPUT_JUMP, 0, INPUT_ATTACK]
+                .iter()
+                .map(|inp| Input {
+                    inp: *inp,
+                    aim: NO_AIM,
+                })
+                .collect(),
+            playback_start: 0,
+            spots: Vec::new(),
+        };
+        let played: Vec<u8> = (0..7)
+            .map(|frame| training.get_recorded_input(frame).inp)
+            .collect();
+        assert_eq!(
+            played,
+            vec![
+                INPUT_JUMP,
+                0,
+                INPUT_ATTACK,
+                INPUT_JUMP,
+                0,
+                INPUT_ATTACK,
+                INPUT_JUMP
+            ]
+        );
+    }
+}
@@ -1,8 +1,16 @@
 use fixed::types::{I32F32, I64F64};
-use fixed_sqrt::FixedSqrt;
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+// how many directions an analog stick can aim in
+pub const AIM_DIRECTIONS: u8 = 64;
+
+// the sine of each direction in the first quarter turn, in 65536ths
+const AIM_SINES: [i32; AIM_DIRECTIONS as usize / 4 + 1] = [
+    0, 6424, 12785, 19024, 25080, 30893, 36410, 41576, 46341, 50660,
+    54491, 57798, 60547, 62714, 64277, 65220, 65536,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct IntVector2D {
     pub x: i32,
     pub y: i32,
@@ -45,6 +53,34 @@ pub struct Hitbox {
     pub height: i32,
 }
 
+// direction 0 points right and they go clockwise, since y points down.
+// table lookups keep this identical on every peer
+pub fn aim_heading(direction: u8, length: i32) -> IntVector2D {
+    let quarter = AIM_DIRECTIONS / 4;
+    let direction = direction % AIM_DIRECTIONS;
+    let cosine = aim_sine((direction + quarter) % AIM_DIRECTIONS);
+    let sine = aim_sine(direction);
+    return IntVector2D {
+        x: (cosine as i64 * length as i64 / 65536) as i32,
+        y: (sine as i64 * length as i64 / 65536) as i32,
+    };
+}
+
+fn aim_sine(direction: u8) -> i32 {
+    let quarter = AIM_DIRECTIONS / 4;
+    let index = match direction / quarter {
+        0 => direction,
+        1 => 2 * quarter - direction,
+        2 => direction - 2 * quarter,
+        _ => 4 * quarter - direction,
+    };
+    let sine = AIM_SINES[index as usize];
+    if direction >= 2 * quarter {
+        return -sine;
+    }
+    return sine;
+}
+
 pub fn do_hitboxes_overlap(a: &Hitbox, b: &Hitbox) -> bool {
     let is_not_overlapping = a.x > b.x + b.width
         || b.x > a.x + a.width
@@ -99,3 +135,67 @@ pub fn input_pressed(check: u8, input: u8, prev_input: u8) -> bool {
 pub fn input_released(check: u8, input: u8, prev_input: u8) -> bool {
     return !input_check(check, input) && input_check(check, prev_input);
 }
+
+// 64-bit FNV-1a, used where both peers must compute the same hash
+pub fn fnv1a_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sine_table_matches_the_real_sine() {
+        for direction in 0..AIM_DIRECTIONS {
+            let turns = direction as f64 / AIM_DIRECTIONS as f64;
+            let expected = (turns * std::f64::consts::TAU).sin() * 65536.0;
+            let difference = (aim_sine(direction) as f64 - expected).abs();
+            assert!(difference <= 1.0, "direction {}", direction);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_point_straight_along_the_axes() {
+        let quarter = AIM_DIRECTIONS / 4;
+        assert_eq!(aim_heading(0, 1000), IntVector2D { x: 1000, y: 0 });
+        assert_eq!(
+            aim_heading(quarter, 1000),
+            IntVector2D { x: 0, y: 1000 }
+        );
+        assert_eq!(
+            aim_heading(2 * quarter, 1000),
+            IntVector2D { x: -1000, y: 0 }
+        );
+        assert_eq!(
+            aim_heading(3 * quarter, 1000),
+            IntVector2D { x: 0, y: -1000 }
+        );
+        assert_eq!(
+            aim_heading(AIM_DIRECTIONS, 1000),
+            aim_heading(0, 1000)
+        );
+    }
+
+    #[test]
+    fn headings_keep_their_length_and_mirror_across_the_axes() {
+        let half = AIM_DIRECTIONS / 2;
+        for direction in 0..AIM_DIRECTIONS {
+            let heading = aim_heading(direction, 100000);
+            let length = (heading.x as f64).hypot(heading.y as f64);
+            assert!((length - 100000.0).abs() <= 2.0, "{}", direction);
+
+            let opposite = aim_heading(direction + half, 100000);
+            assert_eq!(opposite.x, -heading.x, "{}", direction);
+            assert_eq!(opposite.y, -heading.y, "{}", direction);
+            let mirrored = aim_heading(AIM_DIRECTIONS - direction, 100000);
+            assert_eq!(mirrored.x, heading.x, "{}", direction);
+            assert_eq!(mirrored.y, -heading.y, "{}", direction);
+        }
+    }
+}